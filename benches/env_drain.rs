@@ -0,0 +1,97 @@
+//! EnvDrain hot path benchmark
+//!
+//! Each case runs with the module cache and without it (`uncached`).
+//!
+//! run with `cargo bench --bench env_drain`
+extern crate co_slog;
+#[macro_use]
+extern crate slog;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use slog::{Drain, Logger};
+use co_slog::LogBuilder;
+
+const ITERS: u32 = 1_000_000;
+
+/// spec with `n` unrelated directives in front of the matching one
+fn many_directives(n: usize, matching: &str) -> String {
+    let mut spec = String::from("warn");
+    for i in 0..n {
+        spec.push_str(&format!(",crate{}::module{}=trace", i, i));
+    }
+    spec.push(',');
+    spec.push_str(matching);
+    spec
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let ns = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+    println!("{:<48} {:>8} ns/iter", name, ns / u64::from(ITERS));
+}
+
+/// the logger with the module cache and without it, the uncached one looks
+/// the directives up on every record
+fn loggers(spec: &str) -> Vec<(&'static str, Logger)> {
+    [("", true), (", uncached", false)]
+        .iter()
+        .map(|&(suffix, cache)| {
+            let drain = LogBuilder::new(slog::Discard)
+                .parse(spec)
+                .module_cache(cache)
+                .build();
+            (suffix, Logger::root(drain.fuse(), o!()))
+        })
+        .collect()
+}
+
+fn bench(name: &str, spec: &str, f: fn(&Logger, u32)) {
+    for (suffix, logger) in loggers(spec) {
+        let start = Instant::now();
+        for i in 0..ITERS {
+            f(&logger, i);
+        }
+        report(&format!("{}{}", name, suffix), start.elapsed());
+    }
+}
+
+/// the same `ITERS` records split over `threads` threads logging at once
+fn bench_threads(name: &str, spec: &str, threads: u32, f: fn(&Logger, u32)) {
+    for (suffix, logger) in loggers(spec) {
+        let start = Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let logger = logger.clone();
+                thread::spawn(move || for i in 0..ITERS / threads {
+                    f(&logger, i);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        report(&format!("{}{}", name, suffix), start.elapsed());
+    }
+}
+
+fn main() {
+    bench("enabled, 1 directive", "env_drain=debug", |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+    bench("enabled, 100 directives", &many_directives(100, "env_drain=debug"), |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+    bench("disabled, 100 directives", &many_directives(100, "env_drain=info"), |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+    bench("regex, 100 directives", &many_directives(100, "env_drain=debug/^msg 1"), |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+    bench_threads("enabled, 1 directive, 4 threads", "env_drain=debug", 4, |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+    bench_threads("disabled, 100 directives, 4 threads",
+                  &many_directives(100, "env_drain=info"), 4, |l, i| {
+        slog_debug!(l, "msg {}", i)
+    });
+}
@@ -1,22 +1,13 @@
 use co_slog;
 
 pub fn simulate_server() {
-    let log = co_slog::logger();
-
-    let server = log.new(o!("host" => "localhost", "port" => "8080"));
-
-    let _log = co_slog::set_logger(server);
+    let _log = co_slog::with_values(o!("host" => "localhost", "port" => "8080"));
     info!("starting");
     info!("listening");
 
-    let log = co_slog::logger();
-
     join!(
         {
-            let peer1 = log.clone().new(
-                o!("peer_addr" => "8.8.8.8", "port" => "18230"),
-            );
-            let _log = co_slog::set_logger(peer1);
+            let _log = co_slog::with_values(o!("peer_addr" => "8.8.8.8", "port" => "18230"));
             debug!("connected");
             debug!("message received"; "length" => 2);
             debug!("response sent"; "length" => 8);
@@ -24,10 +15,7 @@ pub fn simulate_server() {
         },
 
         {
-            let peer2 = log.clone().new(
-                o!("peer_addr" => "82.9.9.9", "port" => "42381"),
-            );
-            let _log = co_slog::set_logger(peer2);
+            let _log = co_slog::with_values(o!("peer_addr" => "82.9.9.9", "port" => "42381"));
             debug!("connected");
             debug!("message received"; "length" => 2);
             warn!("weak encryption requested"; "algo" => "xor");
@@ -1,4 +1,3 @@
-#[macro_use]
 extern crate may;
 #[macro_use]
 extern crate slog;
@@ -88,7 +87,7 @@ fn server_4() {
         o!("module" => slog::FnValue(|info| info.module()),
            "data" => Foo {a: 10, b: String::from("hello")}),
     );
-    co_slog::set_global_logger(log);
+    let _guard = co_slog::set_global_logger(log);
     // info!("log init done");
 
     common::simulate_server();
@@ -0,0 +1,31 @@
+//! Change the verbosity of a running process
+//!
+//! ```text
+//! RUST_LOG=info cargo run --example verbosity-signals
+//! kill -USR1 <pid>  # debug records show up
+//! kill -USR2 <pid>  # back to info
+//! ```
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate co_slog;
+
+#[cfg(unix)]
+fn main() {
+    use std::time::Duration;
+
+    co_slog::listen_verbosity_signals().expect("failed to install the signal listener");
+    info!("listening to SIGUSR1/SIGUSR2"; "pid" => std::process::id());
+
+    for i in 0.. {
+        info!("tick"; "i" => i);
+        debug!("debug tick"; "i" => i);
+        trace!("trace tick"; "i" => i);
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("the verbosity signals are only available on unix");
+}
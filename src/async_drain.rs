@@ -26,6 +26,8 @@
 //!
 //! A way around this issue is encapsulate the construction of the logger into
 //! it's own function that returns before `std::process::exit` is called.
+//! Another one is to call `Drain::flush` on the drain (or `AsyncGuard::flush`)
+//! before exiting, it waits until the records sent so far are written.
 //!
 //! ```
 //! // ...
@@ -49,13 +51,19 @@
 #![warn(missing_docs)]
 use std::error::Error;
 use std::{io, fmt, thread};
-use std::sync::mpsc::SendError;
-use std::sync::PoisonError;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::collections::VecDeque;
+use std::cell::Cell;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::{Arc, Mutex as StdMutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use take_mut::take;
-use may::sync::{mpsc, Mutex};
-use slog::{self, Drain, Serializer, OwnedKVList, Key, Record, RecordStatic, Level, SingleKV, KV,
-           BorrowedKV};
+use chrono::{DateTime, Local, Utc};
+use may::coroutine;
+use may::sync::{mpsc, Condvar, Mutex};
+use slog::{self, Drain, FlushError, Serializer, Value, OwnedKVList, Key, Record, RecordStatic,
+           Level, SingleKV, KV, BorrowedKV};
+use panic_hook::DrainSection;
 // }}}
 
 // {{{ Serializer
@@ -160,16 +168,12 @@ impl Serializer for ToSendSerializer {
 pub enum AsyncError {
     /// Could not send record to worker thread due to full queue
     Full,
+    /// The worker is shut down and doesn't take records any more
+    WorkerGone,
     /// Fatal problem - mutex or channel poisoning issue
     Fatal(Box<Error>),
 }
 
-impl<T> From<SendError<T>> for AsyncError {
-    fn from(_: SendError<T>) -> AsyncError {
-        AsyncError::Full
-    }
-}
-
 impl<T> From<PoisonError<T>> for AsyncError {
     fn from(err: PoisonError<T>) -> AsyncError {
         AsyncError::Fatal(Box::new(
@@ -182,13 +186,209 @@ pub type AsyncResult<T> = Result<T, AsyncError>;
 
 // }}}
 
+// {{{ Timestamps
+coroutine_local! {
+    /// the log time of the record the worker is writing
+    static RECORD_TIME: Cell<Option<(SystemTime, Instant)>> = {
+        Cell::new(None)
+    }
+}
+
+/// Wall-clock time of the record being written
+///
+/// In the drain called by an `AsyncDrain` worker it's the time the record was
+/// logged, anywhere else it's now.
+pub fn record_time() -> SystemTime {
+    RECORD_TIME.with(|t| t.get()).map_or_else(SystemTime::now, |t| t.0)
+}
+
+/// Monotonic time of the record being written, see `record_time`
+pub fn record_instant() -> Instant {
+    RECORD_TIME.with(|t| t.get()).map_or_else(Instant::now, |t| t.1)
+}
+
+/// `slog_term` timestamp function in local time using `record_time`
+///
+/// ```ignore
+/// let drain = slog_term::FullFormat::new(decorator)
+///     .use_custom_timestamp(co_slog::timestamp_local)
+///     .build();
+/// ```
+pub fn timestamp_local(io: &mut io::Write) -> io::Result<()> {
+    let time: DateTime<Local> = record_time().into();
+    write!(io, "{}", time.format("%b %d %H:%M:%S%.3f"))
+}
+
+/// `slog_term` timestamp function in UTC using `record_time`
+pub fn timestamp_utc(io: &mut io::Write) -> io::Result<()> {
+    let time: DateTime<Utc> = record_time().into();
+    write!(io, "{}", time.format("%b %d %H:%M:%S%.3f"))
+}
+
+/// the `ts` key value
+struct Timestamp(SystemTime);
+
+impl Value for Timestamp {
+    fn serialize(&self, _: &Record, key: Key, serializer: &mut Serializer) -> slog::Result {
+        let time: DateTime<Local> = self.0.into();
+        serializer.emit_arguments(key, &format_args!("{}", time.to_rfc3339()))
+    }
+}
+// }}}
+
+// {{{ Queue
+/// What to do with a record sent to a full `AsyncDrain` channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the worker to make room, in a coroutine only the coroutine
+    /// waits
+    Block,
+    /// Drop the new record
+    DropNewest,
+    /// Drop the oldest queued record to make room for the new one
+    DropOldest,
+}
+
+struct QueueState {
+    msgs: VecDeque<AsyncMsg>,
+    // number of `AsyncMsg::Record` in `msgs`
+    records: usize,
+    closed: bool,
+}
+
+/// bounded channel to the worker
+///
+/// Only the records count for the size, the control messages are always
+/// accepted.
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    size: usize,
+    policy: OverflowPolicy,
+    // records dropped by `OverflowPolicy::DropOldest`
+    evicted: AtomicUsize,
+    // records the wrapped drain panicked on
+    panics: AtomicUsize,
+}
+
+impl Queue {
+    fn new(size: usize, policy: OverflowPolicy) -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                msgs: VecDeque::new(),
+                records: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            size: size,
+            policy: policy,
+            evicted: AtomicUsize::new(0),
+            panics: AtomicUsize::new(0),
+        }
+    }
+
+    fn push_record(&self, r: AsyncRecord) -> AsyncResult<()> {
+        let mut state = self.state.lock()?;
+        loop {
+            if state.closed {
+                return Err(AsyncError::WorkerGone);
+            }
+            if state.records < self.size {
+                break;
+            }
+            match self.policy {
+                OverflowPolicy::Block => state = self.not_full.wait(state)?,
+                OverflowPolicy::DropNewest => return Err(AsyncError::Full),
+                OverflowPolicy::DropOldest => {
+                    let oldest = state.msgs.iter().position(|m| match *m {
+                        AsyncMsg::Record(..) => true,
+                        _ => false,
+                    });
+                    if let Some(pos) = oldest {
+                        state.msgs.remove(pos);
+                        state.records -= 1;
+                        self.evicted.fetch_add(1, Ordering::Relaxed);
+                    }
+                    break;
+                }
+            }
+        }
+        state.msgs.push_back(AsyncMsg::Record(r));
+        state.records += 1;
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn push(&self, msg: AsyncMsg) -> AsyncResult<()> {
+        let mut state = self.state.lock()?;
+        if state.closed {
+            return Err(AsyncError::WorkerGone);
+        }
+        state.msgs.push_back(msg);
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn pop(&self) -> AsyncMsg {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(msg) = state.msgs.pop_front() {
+                let is_record = match msg {
+                    AsyncMsg::Record(..) => true,
+                    _ => false,
+                };
+                if is_record {
+                    state.records -= 1;
+                }
+                drop(state);
+                if is_record {
+                    self.not_full.notify_one();
+                }
+                return msg;
+            }
+            state = self.not_empty.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// no more messages are accepted, the blocked senders are woken up
+    ///
+    /// The pending messages are dropped, so the flush waiters see the
+    /// disconnect.
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.closed = true;
+        state.msgs.clear();
+        state.records = 0;
+        drop(state);
+        self.not_full.notify_all();
+    }
+}
+// }}}
+
 // {{{ AsyncCore
+/// answers `Drain::is_enabled` for the drain moved to the worker
+type LevelHint = Arc<Fn(Level) -> bool + Send + Sync>;
+
+/// takes the records the wrapped drain panicked on
+type FallbackDrain = Box<Drain<Err = slog::Never, Ok = ()> + Send>;
+
 /// `AsyncCore` builder
 pub struct AsyncCoreBuilder<D>
 where
     D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
 {
+    chan_size: usize,
+    overflow_policy: OverflowPolicy,
+    coroutine_worker: bool,
+    ts_key: bool,
+    ts_key_name: Key,
     drain: D,
+    fallback: Option<FallbackDrain>,
+    level_hint: Option<LevelHint>,
 }
 
 impl<D> AsyncCoreBuilder<D>
@@ -196,31 +396,102 @@ where
     D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
 {
     fn new(drain: D) -> Self {
-        AsyncCoreBuilder { drain: drain }
-    }
-
-    fn spawn_thread(self) -> (thread::JoinHandle<()>, mpsc::Sender<AsyncMsg>) {
-        let (tx, rx) = mpsc::channel();
-        let join = thread::spawn(move || loop {
-            match rx.recv().unwrap() {
-                AsyncMsg::Record(r) => {
-                    let rs = RecordStatic {
-                        location: &*r.location,
-                        level: r.level,
-                        tag: &r.tag,
-                    };
-                    self.drain
-                        .log(
-                            &Record::new(&rs, &format_args!("{}", r.msg), BorrowedKV(&r.kv)),
-                            &r.logger_values,
-                        )
-                        .unwrap();
-                }
-                AsyncMsg::Finish => return,
-            }
-        });
+        AsyncCoreBuilder {
+            chan_size: 128,
+            overflow_policy: OverflowPolicy::DropNewest,
+            coroutine_worker: false,
+            ts_key: false,
+            ts_key_name: "ts",
+            drain: drain,
+            fallback: None,
+            level_hint: None,
+        }
+    }
+
+    /// Set channel size used to send logging records to worker thread. What
+    /// happens when the buffer is full depends on the `overflow_policy`, with
+    /// `DropNewest` `AsyncCore` returns `AsyncError::Full`.
+    pub fn chan_size(mut self, s: usize) -> Self {
+        self.chan_size = if s == 0 { 1 } else { s };
+        self
+    }
 
-        (join, tx)
+    /// Set what happens to the records sent when the channel is full
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Run the worker in a may coroutine instead of a dedicated thread
+    ///
+    /// The drain IO then goes through the may scheduler, a drain writing
+    /// with the may IO types doesn't block a scheduler thread.
+    pub fn coroutine_worker(mut self, enable: bool) -> Self {
+        self.coroutine_worker = enable;
+        self
+    }
+
+    /// Add the time the record was logged as the `ts` key
+    ///
+    /// It's formatted as RFC 3339 in local time by the worker. `slog_json`
+    /// with `add_default_keys()` writes its own `ts` key, use `ts_key_name`
+    /// or leave the default keys out to not get it twice.
+    pub fn ts_key(mut self, enable: bool) -> Self {
+        self.ts_key = enable;
+        self
+    }
+
+    /// Set the name of the key added by `ts_key`, `ts` by default
+    pub fn ts_key_name(mut self, name: Key) -> Self {
+        self.ts_key_name = name;
+        self
+    }
+
+    /// Hand the records the wrapped drain panicked on to the fallback drain
+    ///
+    /// The worker keeps running after such a panic in any case, without a
+    /// fallback the record is lost.
+    pub fn fallback<F>(mut self, drain: F) -> Self
+    where
+        F: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.fallback = Some(Box::new(drain));
+        self
+    }
+
+    /// Answer `Drain::is_enabled` with the hint
+    ///
+    /// The wrapped drain runs in the worker thread, so it can't be asked
+    /// directly. Without a hint all the levels are enabled.
+    pub fn level_hint<F>(mut self, hint: F) -> Self
+    where
+        F: Fn(Level) -> bool + Send + Sync + 'static,
+    {
+        self.level_hint = Some(Arc::new(hint));
+        self
+    }
+
+    fn spawn_worker(self) -> (WorkerHandle, Arc<Queue>) {
+        let queue = Arc::new(Queue::new(self.chan_size, self.overflow_policy));
+        let id = register_worker(queue.clone());
+        let exit = WorkerExit {
+            id: id,
+            queue: queue.clone(),
+        };
+        let drain = self.drain;
+        let fallback = self.fallback;
+        let join = if self.coroutine_worker {
+            let builder = coroutine::Builder::new()
+                .name("co_slog-async".to_owned())
+                .stack_size(WORKER_STACK_SIZE);
+            let co = go!(builder, move || run_worker(drain, fallback, exit))
+                .expect("failed to spawn the AsyncDrain worker coroutine");
+            WorkerHandle::Coroutine(co)
+        } else {
+            WorkerHandle::Thread(thread::spawn(move || run_worker(drain, fallback, exit)))
+        };
+
+        (join, queue)
     }
 
     /// Build `AsyncCore`
@@ -230,29 +501,37 @@ where
     }
 
     /// Build `AsyncCore`
-    pub fn build_no_guard(self) -> AsyncCore {
-        let (join, tx) = self.spawn_thread();
+    pub fn build_no_guard(mut self) -> AsyncCore {
+        let level_hint = self.level_hint.take();
+        let ts_key = if self.ts_key { Some(self.ts_key_name) } else { None };
+        let (join, queue) = self.spawn_worker();
 
         AsyncCore {
-            ref_sender: tx,
+            queue: queue,
             join: Mutex::new(Some(join)),
+            level_hint: level_hint,
+            ts_key: ts_key,
         }
     }
 
     /// Build `AsyncCore` with `AsyncGuard`
     ///
     /// See `AsyncGuard` for more information.
-    pub fn build_with_guard(self) -> (AsyncCore, AsyncGuard) {
-        let (join, tx) = self.spawn_thread();
+    pub fn build_with_guard(mut self) -> (AsyncCore, AsyncGuard) {
+        let level_hint = self.level_hint.take();
+        let ts_key = if self.ts_key { Some(self.ts_key_name) } else { None };
+        let (join, queue) = self.spawn_worker();
 
         (
             AsyncCore {
-                ref_sender: tx.clone(),
+                queue: queue.clone(),
                 join: Mutex::new(None),
+                level_hint: level_hint,
+                ts_key: ts_key,
             },
             AsyncGuard {
                 join: Some(join),
-                tx: tx,
+                queue: queue,
             },
         )
     }
@@ -275,15 +554,26 @@ where
 pub struct AsyncGuard {
     // Should always be `Some`. `None` only
     // after `drop`
-    join: Option<thread::JoinHandle<()>>,
-    tx: mpsc::Sender<AsyncMsg>,
+    join: Option<WorkerHandle>,
+    queue: Arc<Queue>,
+}
+
+impl AsyncGuard {
+    /// Wait until the records sent so far are written
+    ///
+    /// Sends a barrier to the worker, which calls the `flush` of the wrapped
+    /// drain when it gets there. A wrapped drain without `flush` support
+    /// counts as flushed. Gives up after `timeout`.
+    pub fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        flush_queue(&self.queue, Some(timeout))
+    }
 }
 
 impl Drop for AsyncGuard {
     fn drop(&mut self) {
         let _err: Result<(), Box<Error>> = {
             || {
-                let _ = self.tx.send(AsyncMsg::Finish);
+                let _ = self.queue.push(AsyncMsg::Finish);
                 self.join.take().unwrap().join().map_err(|_| {
                     io::Error::new(
                         io::ErrorKind::BrokenPipe,
@@ -308,8 +598,10 @@ impl Drop for AsyncGuard {
 /// handling all previous `Record`s sent to it). If you can't tolerate the
 /// delay, make sure you drop it eg. in another thread.
 pub struct AsyncCore {
-    ref_sender: mpsc::Sender<AsyncMsg>,
-    join: Mutex<Option<thread::JoinHandle<()>>>,
+    queue: Arc<Queue>,
+    join: Mutex<Option<WorkerHandle>>,
+    level_hint: Option<LevelHint>,
+    ts_key: Option<Key>,
 }
 
 impl AsyncCore {
@@ -331,17 +623,19 @@ impl AsyncCore {
         AsyncCoreBuilder::new(drain)
     }
 
-    fn get_sender(&self) -> &mpsc::Sender<AsyncMsg> {
-        &self.ref_sender
-    }
-
     /// Send `AsyncRecord` to a worker thread.
     fn send(&self, r: AsyncRecord) -> AsyncResult<()> {
-        let sender = self.get_sender();
+        self.queue.push_record(r)
+    }
 
-        sender.send(AsyncMsg::Record(r))?;
+    /// number of queued records dropped by `OverflowPolicy::DropOldest`
+    fn take_evicted(&self) -> usize {
+        self.queue.evicted.swap(0, Ordering::Relaxed)
+    }
 
-        Ok(())
+    /// Number of the times the wrapped drain panicked
+    pub fn panics(&self) -> usize {
+        self.queue.panics.load(Ordering::Relaxed)
     }
 }
 
@@ -350,11 +644,18 @@ impl Drain for AsyncCore {
     type Err = AsyncError;
 
     fn log(&self, record: &Record, logger_values: &OwnedKVList) -> AsyncResult<()> {
+        // the worker might get to the record much later
+        let time = SystemTime::now();
+        let instant = Instant::now();
 
         let mut ser = ToSendSerializer::new();
         record.kv().serialize(record, &mut ser).expect(
             "`ToSendSerializer` can't fail",
         );
+        let kv: Box<KV + Send> = match self.ts_key {
+            Some(key) => Box::new((ser.finish(), SingleKV(key, Timestamp(time)))),
+            None => ser.finish(),
+        };
 
         self.send(AsyncRecord {
             msg: fmt::format(*record.msg()),
@@ -362,9 +663,20 @@ impl Drain for AsyncCore {
             location: Box::new(*record.location()),
             tag: String::from(record.tag()),
             logger_values: logger_values.clone(),
-            kv: ser.finish(),
+            kv: kv,
+            time: time,
+            instant: instant,
         })
     }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.level_hint.as_ref().map_or(true, |hint| hint(level))
+    }
+
+    /// Wait until the records sent so far are written, see `AsyncGuard::flush`
+    fn flush(&self) -> Result<(), FlushError> {
+        flush_queue(&self.queue, None)
+    }
 }
 
 struct AsyncRecord {
@@ -374,19 +686,204 @@ struct AsyncRecord {
     tag: String,
     logger_values: OwnedKVList,
     kv: Box<KV + Send>,
+    time: SystemTime,
+    instant: Instant,
 }
 
 enum AsyncMsg {
     Record(AsyncRecord),
+    Flush(mpsc::Sender<Result<(), FlushError>>),
     Finish,
 }
 
+/// unique id source for the live workers
+static NEXT_WORKER_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    /// all the live workers, used to flush them on panic
+    static ref WORKERS: StdMutex<Vec<(usize, Arc<Queue>)>> = {
+        StdMutex::new(Vec::new())
+    };
+}
+
+fn register_worker(queue: Arc<Queue>) -> usize {
+    let id = NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed);
+    let mut workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+    workers.push((id, queue));
+    id
+}
+
+fn unregister_worker(id: usize) {
+    let mut workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+    workers.retain(|w| w.0 != id);
+}
+
+/// stack size of the worker coroutine, the drains format and serialize on it
+const WORKER_STACK_SIZE: usize = 0x8000;
+
+/// the worker thread or coroutine
+enum WorkerHandle {
+    Thread(thread::JoinHandle<()>),
+    Coroutine(coroutine::JoinHandle<()>),
+}
+
+impl WorkerHandle {
+    fn join(self) -> thread::Result<()> {
+        match self {
+            WorkerHandle::Thread(join) => join.join(),
+            WorkerHandle::Coroutine(join) => join.join(),
+        }
+    }
+}
+
+fn log_record<D>(drain: &D, r: &AsyncRecord)
+where
+    D: Drain<Err = slog::Never, Ok = ()> + ?Sized,
+{
+    // a panic in here must not log back into the same drain
+    let _section = DrainSection::enter();
+    let rs = RecordStatic {
+        location: &*r.location,
+        level: r.level,
+        tag: &r.tag,
+    };
+    let _ = drain.log(
+        &Record::new(&rs, &format_args!("{}", r.msg), BorrowedKV(&r.kv)),
+        &r.logger_values,
+    );
+}
+
+/// log the queued records until `AsyncMsg::Finish`
+///
+/// A panic of the drain only loses the record (or hands it to the fallback),
+/// the worker goes on with the next one.
+fn run_worker<D>(drain: D, fallback: Option<FallbackDrain>, exit: WorkerExit)
+where
+    D: Drain<Err = slog::Never, Ok = ()>,
+{
+    WORKER_ID.with(|id| id.set(Some(exit.id)));
+    loop {
+        match exit.queue.pop() {
+            AsyncMsg::Record(r) => {
+                RECORD_TIME.with(|t| t.set(Some((r.time, r.instant))));
+                let res = panic::catch_unwind(AssertUnwindSafe(|| log_record(&drain, &r)));
+                if res.is_err() {
+                    exit.queue.panics.fetch_add(1, Ordering::Relaxed);
+                    if let Some(ref fallback) = fallback {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                            log_record(&**fallback, &r)
+                        }));
+                    }
+                }
+                RECORD_TIME.with(|t| t.set(None));
+            }
+            AsyncMsg::Flush(done) => {
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let _section = DrainSection::enter();
+                    match drain.flush() {
+                        // the barrier is reached, nothing more to write out
+                        Err(FlushError::NotSupported) => Ok(()),
+                        res => res,
+                    }
+                }));
+                let _ = done.send(res.unwrap_or_else(|_| {
+                    exit.queue.panics.fetch_add(1, Ordering::Relaxed);
+                    Err(FlushError::Io(
+                        io::Error::new(io::ErrorKind::Other, "Logging drain flush panicked"),
+                    ))
+                }));
+            }
+            AsyncMsg::Finish => return,
+        }
+    }
+}
+
+/// closes the queue and unregisters the worker when it exits, even by a panic
+struct WorkerExit {
+    id: usize,
+    queue: Arc<Queue>,
+}
+
+impl Drop for WorkerExit {
+    fn drop(&mut self) {
+        self.queue.close();
+        unregister_worker(self.id);
+    }
+}
+
+/// send a barrier to the worker and wait for the flush result
+fn flush_queue(queue: &Queue, timeout: Option<Duration>) -> Result<(), FlushError> {
+    let gone = || {
+        FlushError::Io(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "Logging worker is gone",
+        ))
+    };
+
+    let (done, wait) = mpsc::channel();
+    queue.push(AsyncMsg::Flush(done)).map_err(|_| gone())?;
+    match timeout {
+        Some(timeout) => match wait.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(FlushError::Io(
+                io::Error::new(io::ErrorKind::TimedOut, "Logging worker flush timeout"),
+            )),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(gone()),
+        },
+        None => wait.recv().unwrap_or_else(|_| Err(gone())),
+    }
+}
+
+coroutine_local! {
+    /// id of the worker running in the thread or coroutine
+    static WORKER_ID: Cell<Option<usize>> = {
+        Cell::new(None)
+    }
+}
+
+/// is the caller an `AsyncDrain` worker
+pub(crate) fn is_worker() -> bool {
+    WORKER_ID.with(|id| id.get().is_some())
+}
+
+/// Wait until all the live workers have handled the records sent so far
+///
+/// Gives up after `timeout`. A worker doesn't wait for itself.
+pub(crate) fn flush_all(timeout: Duration) {
+    let me = WORKER_ID.with(|id| id.get());
+    let workers: Vec<_> = {
+        let workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+        workers
+            .iter()
+            .filter(|w| Some(w.0) != me)
+            .map(|w| w.1.clone())
+            .collect()
+    };
+
+    let waits: Vec<_> = workers
+        .iter()
+        .filter_map(|queue| {
+            let (done, wait) = mpsc::channel();
+            queue.push(AsyncMsg::Flush(done)).ok().map(|_| wait)
+        })
+        .collect();
+
+    let deadline = Instant::now() + timeout;
+    for wait in waits {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let _ = wait.recv_timeout(deadline - now);
+    }
+}
+
 impl Drop for AsyncCore {
     fn drop(&mut self) {
         let _err: Result<(), Box<Error>> = {
             || {
                 if let Some(join) = self.join.lock()?.take() {
-                    let _ = self.get_sender().send(AsyncMsg::Finish);
+                    let _ = self.queue.push(AsyncMsg::Finish);
                     join.join().map_err(|_| {
                         io::Error::new(
                             io::ErrorKind::BrokenPipe,
@@ -417,13 +914,65 @@ where
         AsyncBuilder { core: AsyncCoreBuilder::new(drain) }
     }
 
-    /// Set channel size used to send logging records to worker thread. When
-    /// buffer is full `AsyncCore` will start returning `AsyncError::Full`.
-    // pub fn chan_size(self, s: usize) -> Self {
-    //     AsyncBuilder {
-    //         core: self.core.chan_size(s),
-    //     }
-    // }
+    /// Answer `Drain::is_enabled` with the hint, see `AsyncCoreBuilder::level_hint`
+    ///
+    /// ```ignore
+    /// let (drain, handle) = co_slog::EnvDrain::new(drain).build_with_handle();
+    /// let drain = co_slog::AsyncDrain::new(drain.fuse())
+    ///     .level_hint(move |level| handle.is_enabled(level))
+    ///     .build();
+    /// ```
+    pub fn level_hint<F>(self, hint: F) -> Self
+    where
+        F: Fn(Level) -> bool + Send + Sync + 'static,
+    {
+        AsyncBuilder { core: self.core.level_hint(hint) }
+    }
+
+    /// Set channel size used to send logging records to worker thread. What
+    /// happens when the buffer is full depends on the `overflow_policy`.
+    pub fn chan_size(self, s: usize) -> Self {
+        AsyncBuilder { core: self.core.chan_size(s) }
+    }
+
+    /// Set what happens to the records sent when the channel is full
+    ///
+    /// See `OverflowPolicy`, the default is `DropNewest`.
+    pub fn overflow_policy(self, policy: OverflowPolicy) -> Self {
+        AsyncBuilder { core: self.core.overflow_policy(policy) }
+    }
+
+    /// Run the worker in a may coroutine instead of a dedicated thread
+    ///
+    /// See `AsyncCoreBuilder::coroutine_worker`.
+    pub fn coroutine_worker(self, enable: bool) -> Self {
+        AsyncBuilder { core: self.core.coroutine_worker(enable) }
+    }
+
+    /// Add the time the record was logged as the `ts` key
+    ///
+    /// See `AsyncCoreBuilder::ts_key`, for `slog_term` use `timestamp_local`
+    /// or `timestamp_utc` instead. With `slog_json` turn off its
+    /// `add_default_keys()` or rename the key, see `ts_key_name`.
+    pub fn ts_key(self, enable: bool) -> Self {
+        AsyncBuilder { core: self.core.ts_key(enable) }
+    }
+
+    /// Set the name of the key added by `ts_key`, `ts` by default
+    pub fn ts_key_name(self, name: Key) -> Self {
+        AsyncBuilder { core: self.core.ts_key_name(name) }
+    }
+
+    /// Hand the records the wrapped drain panicked on to the fallback drain
+    ///
+    /// See `AsyncCoreBuilder::fallback`.
+    pub fn fallback<F>(self, drain: F) -> Self
+    where
+        F: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        AsyncBuilder { core: self.core.fallback(drain) }
+    }
+
     /// Complete building `AsyncDrain`
     pub fn build(self) -> AsyncDrain {
         AsyncDrain {
@@ -458,17 +1007,20 @@ where
 /// Async drain
 ///
 /// `AsyncDrain` will send all the logging records to a wrapped drain running in
-/// another thread.
+/// another thread, or coroutine (see `AsyncBuilder::coroutine_worker`).
 ///
-/// `AsyncDrain` never returns `AsyncError::Full`.
+/// `AsyncDrain` never returns `AsyncError::Full`. It returns
+/// `AsyncError::WorkerGone` once the worker is shut down (e.g. by dropping the
+/// `AsyncGuard`).
 ///
 /// `Record`s are passed to the worker thread through a channel with a bounded
-/// size (see `AsyncBuilder::chan_size`). On channel overflow `AsyncDrain` will
-/// start dropping `Record`s and log a message informing about it after
-/// sending more `Record`s is possible again. The exact details of handling
-/// overflow is implementation defined, might change and should not be relied
-/// on, other than message won't be dropped as long as channel does not
-/// overflow.
+/// size (see `AsyncBuilder::chan_size`). On channel overflow `AsyncDrain`
+/// waits for room or drops `Record`s, see `AsyncBuilder::overflow_policy`.
+/// The dropped `Record`s are counted and a message informing about it is
+/// logged after sending more `Record`s is possible again.
+/// The exact details of handling overflow is implementation defined, might
+/// change and should not be relied on, other than message won't be dropped as
+/// long as channel does not overflow.
 ///
 /// Any messages reported by `AsyncDrain` will contain `slog-async` logging `Record`
 /// tag to allow easy custom handling.
@@ -496,8 +1048,15 @@ impl AsyncDrain {
         AsyncBuilder::new(drain)
     }
 
+    /// Number of the times the wrapped drain panicked
+    ///
+    /// The worker survives the panics, see `AsyncBuilder::fallback`.
+    pub fn panics(&self) -> usize {
+        self.core.panics()
+    }
+
     fn push_dropped(&self, logger_values: &OwnedKVList) -> AsyncResult<()> {
-        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        let dropped = self.dropped.swap(0, Ordering::Relaxed) + self.core.take_evicted();
         if dropped > 0 {
             match self.core.log(
                 &record!(
@@ -544,6 +1103,18 @@ impl Drain for AsyncDrain {
 
         Ok(())
     }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.core.is_enabled(level)
+    }
+
+    /// Wait until the records sent so far are written
+    ///
+    /// The pending "dropped messages" record is sent first.
+    fn flush(&self) -> Result<(), FlushError> {
+        let _ = self.push_dropped(&o!().into());
+        self.core.flush()
+    }
 }
 
 impl Drop for AsyncDrain {
@@ -554,4 +1125,325 @@ impl Drop for AsyncDrain {
 
 // }}}
 
+#[cfg(test)]
+mod tests {
+    use std::{io, thread};
+    use std::cell::Cell;
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use slog::{Drain, FlushError, Level, Logger};
+    use super::slog;
+
+    use super::{AsyncDrain, AsyncError, AsyncGuard, OverflowPolicy};
+    use global::{self, set_global_logger};
+    use panic_hook::{PanicHook, TestHook};
+    use test_drains::{Counter, Recorder};
+
+    /// record, the first record waits until the gate is opened
+    struct Gate {
+        first: Cell<bool>,
+        entered: mpsc::Sender<()>,
+        open: mpsc::Receiver<()>,
+        logged: Recorder,
+    }
+
+    impl Drain for Gate {
+        type Ok = ();
+        type Err = slog::Never;
+        fn log(&self, r: &slog::Record, values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            if self.first.get() {
+                self.first.set(false);
+                let _ = self.entered.send(());
+                let _ = self.open.recv();
+            }
+            self.logged.log(r, values)
+        }
+    }
+
+    /// a drain stuck in its first record, with room for `size` more
+    fn gated(
+        policy: OverflowPolicy,
+        size: usize,
+    ) -> (Arc<AsyncDrain>, Logger, AsyncGuard, mpsc::Sender<()>, Recorder) {
+        let (entered, wait_entered) = mpsc::channel();
+        let (open, wait_open) = mpsc::channel();
+        let logged = Recorder::new();
+        let gate = Gate {
+            first: Cell::new(true),
+            entered: entered,
+            open: wait_open,
+            logged: logged.clone(),
+        };
+        let (drain, guard) = AsyncDrain::new(gate)
+            .chan_size(size)
+            .overflow_policy(policy)
+            .build_with_guard();
+        let drain = Arc::new(drain);
+        let logger = Logger::root(drain.clone().ignore_res(), o!());
+
+        slog_info!(logger, "0");
+        wait_entered.recv().unwrap();
+        (drain, logger, guard, open, logged)
+    }
+
+    /// the messages, with the "dropped messages" notices as `dropped <count>`
+    fn messages(logged: &Recorder) -> Vec<String> {
+        logged
+            .entries()
+            .into_iter()
+            .map(|e| if e.msg.starts_with("slog-async: logger dropped messages") {
+                format!("dropped {}", e.get("count").unwrap_or(""))
+            } else {
+                e.msg
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overflow_drop_newest() {
+        let (drain, logger, guard, open, logged) = gated(OverflowPolicy::DropNewest, 2);
+        for i in 1..5 {
+            slog_info!(logger, "{}", i);
+        }
+        // "3", "4" and the notice about "3" tried before "4"
+        assert_eq!(drain.dropped.load(Ordering::Relaxed), 3);
+
+        open.send(()).unwrap();
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        slog_info!(logger, "5");
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(
+            messages(&logged),
+            vec!["0", "1", "2", "dropped 3", "5"]
+        );
+    }
+
+    #[test]
+    fn overflow_drop_oldest() {
+        let (_drain, logger, guard, open, logged) = gated(OverflowPolicy::DropOldest, 2);
+        for i in 1..5 {
+            slog_info!(logger, "{}", i);
+        }
+
+        open.send(()).unwrap();
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        // the evicted records are reported like the dropped ones, the notice
+        // sent before "4" takes the place of "2" and "4" the one of "3"
+        slog_info!(logger, "5");
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(
+            messages(&logged),
+            vec!["0", "dropped 1", "4", "dropped 2", "5"]
+        );
+    }
+
+    #[test]
+    fn overflow_block() {
+        let (_drain, logger, guard, open, logged) = gated(OverflowPolicy::Block, 1);
+        slog_info!(logger, "1");
+
+        let sent = Arc::new(AtomicBool::new(false));
+        let blocked = {
+            let logger = logger.clone();
+            let sent = sent.clone();
+            thread::spawn(move || {
+                slog_info!(logger, "2");
+                sent.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(!sent.load(Ordering::SeqCst));
+
+        open.send(()).unwrap();
+        blocked.join().unwrap();
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(messages(&logged), vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn ts_key_name() {
+        let logged = Recorder::new();
+        let (drain, guard) = AsyncDrain::new(logged.clone())
+            .ts_key(true)
+            .ts_key_name("time")
+            .build_with_guard();
+        let logger = Logger::root(drain.ignore_res(), o!());
+
+        slog_info!(logger, "msg"; "user" => "joe");
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        let mut keys: Vec<_> = logged.entries()[0].kv.iter().map(|kv| kv.0.clone()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["time", "user"]);
+    }
+
+    #[test]
+    fn drain_flush() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let drain = Arc::new(AsyncDrain::new(Counter(count.clone())).build());
+        let logger = Logger::root(drain.clone().ignore_res(), o!());
+        for i in 0..3 {
+            slog_info!(logger, "record {}", i);
+        }
+        // `Counter` has no flush of its own
+        assert!(drain.flush().is_ok());
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn flush_timeout() {
+        let (_drain, _logger, guard, open, logged) = gated(OverflowPolicy::DropNewest, 2);
+        match guard.flush(Duration::from_millis(50)) {
+            Err(FlushError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
+        open.send(()).unwrap();
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(messages(&logged), vec!["0"]);
+    }
+
+    #[test]
+    fn coroutine_worker() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (drain, guard) = AsyncDrain::new(Counter(count.clone()))
+            .coroutine_worker(true)
+            .build_with_guard();
+        let logger = Logger::root(drain.ignore_res(), o!());
+        for i in 0..3 {
+            slog_info!(logger, "record {}", i);
+        }
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn coroutine_worker_joined_by_guard() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (drain, guard) = AsyncDrain::new(Counter(count.clone()))
+            .coroutine_worker(true)
+            .build_with_guard();
+        let drain = Arc::new(drain);
+        let logger = Logger::root(drain.clone().ignore_res(), o!());
+        for i in 0..3 {
+            slog_info!(logger, "record {}", i);
+        }
+
+        // the pending records are written before the worker exits
+        drop(guard);
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+        let res = drain.log(
+            &record!(Level::Info, "", &format_args!("late"), b!()),
+            &o!().into(),
+        );
+        match res {
+            Err(AsyncError::WorkerGone) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn overflow_block_parks_coroutine() {
+        const SENDERS: usize = 256;
+        let (_drain, logger, guard, open, logged) = gated(OverflowPolicy::Block, 1);
+        slog_info!(logger, "1");
+
+        // more blocked senders than scheduler threads
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|i| {
+                let logger = logger.clone();
+                go!(move || slog_info!(logger, "sender {}", i))
+            })
+            .collect();
+
+        // the scheduler still runs the other coroutines
+        let (done, wait_done) = mpsc::channel();
+        go!(move || done.send(()).unwrap());
+        assert!(wait_done.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(messages(&logged).len(), 0);
+
+        open.send(()).unwrap();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(messages(&logged).len(), 2 + SENDERS);
+    }
+
+    #[test]
+    fn worker_gone_after_guard() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (drain, guard) = AsyncDrain::new(Counter(count.clone())).build_with_guard();
+        drop(guard);
+
+        let res = drain.log(
+            &record!(Level::Info, "", &format_args!("late"), b!()),
+            &o!().into(),
+        );
+        match res {
+            Err(AsyncError::WorkerGone) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+    }
+
+    /// count the records and panic on each of them
+    struct Panicker(Arc<AtomicUsize>);
+
+    impl Drain for Panicker {
+        type Ok = ();
+        type Err = slog::Never;
+        fn log(&self, _: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            panic!("broken drain");
+        }
+    }
+
+    #[test]
+    fn worker_survives_panics() {
+        let tried = Arc::new(AtomicUsize::new(0));
+        let fallback = Arc::new(AtomicUsize::new(0));
+        let (drain, guard) = AsyncDrain::new(Panicker(tried.clone()))
+            .fallback(Counter(fallback.clone()))
+            .build_with_guard();
+        let drain = Arc::new(drain);
+        let logger = Logger::root(drain.clone().ignore_res(), o!());
+
+        for i in 0..3 {
+            slog_info!(logger, "record {}", i);
+        }
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(tried.load(Ordering::Relaxed), 3);
+        assert_eq!(drain.panics(), 3);
+        assert_eq!(fallback.load(Ordering::Relaxed), 3);
+
+        // the worker is still there
+        slog_info!(logger, "one more");
+        assert!(guard.flush(Duration::from_secs(5)).is_ok());
+        assert_eq!(tried.load(Ordering::Relaxed), 4);
+        assert_eq!(fallback.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn panic_hook_stays_bounded() {
+        let _lock = global::test_lock();
+        let _hook = TestHook::install(PanicHook::new().flush_timeout(Duration::from_secs(10)));
+
+        let tried = Arc::new(AtomicUsize::new(0));
+        let (drain, guard) = AsyncDrain::new(Panicker(tried.clone())).build_with_guard();
+        let logger = Logger::root(drain.ignore_res(), o!());
+        // the hook would log the panic into the same broken drain
+        let _global = set_global_logger(logger.clone());
+
+        let start = Instant::now();
+        for i in 0..3 {
+            slog_info!(logger, "record {}", i);
+        }
+        assert!(guard.flush(Duration::from_secs(10)).is_ok());
+        assert_eq!(tried.load(Ordering::Relaxed), 3);
+        // no self flush of the worker waiting out the hook timeout
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
+
 // vim: foldmethod=marker foldmarker={{{,}}}
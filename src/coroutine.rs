@@ -0,0 +1,61 @@
+//! Coroutine helpers that carry the logging scope into the child
+//!
+//! A newly spawned coroutine starts with an empty scope stack and would log
+//! through the global logger. The functions here capture the logger of the
+//! current scope at spawn time and push it in the child before running the
+//! closure, so the scope context follows the work.
+
+use may::coroutine::{self, JoinHandle};
+use slog::Logger;
+use {current_scope, ScopeGuard};
+
+/// run `f` with the inherited `Logger` (if any) pushed on the scope stack
+pub(crate) fn with_parent<F, T>(parent: Option<Logger>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let _guard = parent.map(ScopeGuard::new);
+    f()
+}
+
+/// Spawn a coroutine that inherits the current scope logger
+///
+/// This is a wrapper of `may::coroutine::spawn`, the child would log through
+/// the same `Logger` as the parent until it sets its own.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let parent = current_scope();
+    go!(move || with_parent(parent, f))
+}
+
+/// Scope to spawn coroutines that inherit the current scope logger
+///
+/// See `co_slog::coroutine::scope`.
+pub struct Scope<'a, 'b: 'a> {
+    inner: &'a coroutine::Scope<'b>,
+}
+
+impl<'a, 'b> Scope<'a, 'b> {
+    /// Spawn a scoped coroutine that inherits the current scope logger
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'b,
+    {
+        let parent = current_scope();
+        go!(self.inner, move || with_parent(parent, f));
+    }
+}
+
+/// Create a coroutine scope, it's a wrapper of `may::coroutine::scope`
+///
+/// All the coroutines spawned through the passed in `Scope` inherit the scope
+/// logger of the spawner, and are joined before this function returns.
+pub fn scope<'b, F, R>(f: F) -> R
+where
+    F: for<'a> FnOnce(&Scope<'a, 'b>) -> R,
+{
+    coroutine::scope(|s| f(&Scope { inner: s }))
+}
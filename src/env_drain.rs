@@ -27,8 +27,19 @@
 //! The path to the module is rooted in the name of the crate it was compiled
 //! for, so if your program is contained in a file `hello.rs`, for example, to
 //! turn on logging for this file you would use a value of `RUST_LOG=hello`.
-//! Furthermore, this path is a prefix-search, so all modules nested in the
-//! specified module will also have logging enabled.
+//! Furthermore, all modules nested in the specified module will also have
+//! logging enabled. The path is matched at the `::` boundaries, so `hello`
+//! covers `hello::world` but not `hello_world`.
+//!
+//! The path may contain `*` globs, matching any chars, e.g. `*::db` matches
+//! the `db` module of every crate and `hyper::*` the modules nested in
+//! `hyper`. A directive starting with `-` excludes the module, `-hyper` is the
+//! same as `hyper=off`.
+//!
+//! When several directives match a module the one with the most path segments
+//! wins, then the one with the most segments without globs, so
+//! `tokio_postgres=info,*::db=trace` logs `tokio_postgres::db` at `trace`.
+//! Between equal ones the last in the spec wins.
 //!
 //! The actual `log_level` is optional to specify. If omitted, all logging will
 //! be enabled. If specified, it must be one of the strings `debug`, `error`,
@@ -45,14 +56,42 @@
 //! * `hello=debug` turns on debug logging for 'hello'
 //! * `hello,std::option` turns on hello, and std's option logging
 //! * `error,hello=warn` turn on global error logging and also warn for hello
+//! * `debug,-hyper,*::db=trace` turn on global debug logging, silence hyper
+//!   and turn on trace logging for all the `db` modules
+//!
+//! ## Predicates
+//!
+//! A directive may select records by their key-values, both the record ones
+//! and the ones of the logger (e.g. pushed to the logging scope). The
+//! predicates are put in `[]` after the module path, as a comma-separated
+//! list of `key=value`, and all of them must match the formatted value:
+//!
+//! * `app::http[peer_addr=8.8.8.8]=trace` turns on trace logging for
+//!   'app::http' for the records of the peer 8.8.8.8
+//! * `info,[user_id=42]=debug` turns on global info logging and debug logging
+//!   for the records of the user 42
+//!
+//! The record metadata is selected by the reserved `@` keys:
+//!
+//! * `@tag=audit` matches the records tagged `audit`
+//! * `@file=src/db/*.rs` matches the source file, `*` matches any chars and
+//!   `?` a single char
+//! * `@line=10-20` matches the source line range, inclusive, either end can
+//!   be left out (`10-`, `-20`) and a single line is `@line=10`
+//!
+//! For example `info,[@tag=slog-async]=off` silences the records reported by
+//! `AsyncDrain`.
+//!
+//! Directives with predicates take precedence over the plain ones.
 //!
 //! ## Filtering results
 //!
 //! A RUST_LOG directive may include a regex filter. The syntax is to append `/`
 //! followed by a regex. Each message is checked against the regex, and is only
 //! logged if it matches. Note that the matching is done after formatting the
-//! log string but before adding any logging meta-data. There is a single filter
-//! for all modules.
+//! log string but before adding any logging meta-data. Each directive has its
+//! own filter, which applies to the modules selected by that directive. A `,`
+//! inside the regex is written as `\,`.
 //!
 //! Some examples:
 //!
@@ -63,8 +102,13 @@
 //! * `hello=debug/foo*foo` turns on debug logging for 'hello' where the log
 //!   message includes 'foofoo' or 'fofoo' or 'fooooooofoo', etc.
 //! * `error,hello=warn/[0-9] scopes` turn on global error logging and also
-//!   warn for hello. In both cases the log message must include a single digit
+//!   warn for hello. For hello the log message must include a single digit
 //!   number followed by 'scopes'.
+//! * `hello=debug/foo,world=info/bar` turns on debug logging for 'hello' where
+//!   the log message includes 'foo' and info logging for 'world' where the log
+//!   message includes 'bar'.
+//! * `hello/a\,b` turns on all logging for 'hello' where the log message
+//!   includes 'a,b'.
 
 #![doc(html_logo_url = "http://www.rust-lang.org/logos/rust-logo-128x128-blk-v2.png",
        html_favicon_url = "http://www.rust-lang.org/favicon.ico",
@@ -72,17 +116,24 @@
 #![cfg_attr(test, deny(warnings))]
 
 
-use std::env;
+use std::{env, fmt};
+use std::fmt::Write;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use slog_term;
 use regex::Regex;
+use crossbeam::epoch::{self, Atomic, Owned};
 use mutex_drain::MutexDrain;
-use slog::{self, Drain, FilterLevel};
+use slog::{self, Drain, FilterLevel, Key, Serializer, KV};
 
 /// `EnvDrain` drain.
 pub struct EnvDrain<T: Drain> {
     drain: T,
-    directives: Vec<LogDirective>,
-    filter: Option<Regex>,
+    rules: Arc<RulesCell>,
+    module_cache: bool,
 }
 
 /// LogBuilder acts as builder for initializing the EnvDrain.
@@ -91,7 +142,10 @@ pub struct EnvDrain<T: Drain> {
 pub struct LogBuilder<T: Drain> {
     drain: T,
     directives: Vec<LogDirective>,
-    filter: Option<Regex>,
+    default_level: FilterLevel,
+    // a source of the `or_env`/`or_spec` chain is found
+    has_source: bool,
+    module_cache: bool,
 }
 
 impl<T: Drain> LogBuilder<T> {
@@ -100,8 +154,74 @@ impl<T: Drain> LogBuilder<T> {
         LogBuilder {
             drain: d,
             directives: Vec::new(),
-            filter: None,
+            default_level: FilterLevel::Error,
+            has_source: false,
+            module_cache: true,
+        }
+    }
+
+    /// Initializes the log builder with the directives of the environment
+    /// variable `name`
+    ///
+    /// Fallbacks can be chained, the first source that is set wins:
+    ///
+    /// ```ignore
+    /// LogBuilder::from_env(drain, "MYAPP_LOG").or_env("RUST_LOG").or_spec("info")
+    /// ```
+    pub fn from_env(d: T, name: &str) -> Self {
+        LogBuilder::new(d).or_env(name)
+    }
+
+    /// Parses the environment variable `name`, if no previous source of the
+    /// chain is set
+    pub fn or_env(self, name: &str) -> Self {
+        match env::var(name) {
+            Ok(ref s) if !s.is_empty() => self.or_spec(s),
+            _ => self,
+        }
+    }
+
+    /// Parses the directives string, if no previous source of the chain is set
+    pub fn or_spec(self, spec: &str) -> Self {
+        if self.has_source {
+            return self;
         }
+        let mut builder = self.parse(spec);
+        builder.has_source = true;
+        builder
+    }
+
+    /// Sets the level used when there is no directive, `Error` by default
+    pub fn default_level(mut self, level: FilterLevel) -> Self {
+        self.default_level = level;
+        self
+    }
+
+    /// Raises (positive) or lowers (negative) the default level by `v` steps
+    ///
+    /// Maps the `-v`/`-vv`/`-q` counts of a CLI onto the default level, it's
+    /// relative to the `default_level` set before.
+    pub fn verbosity(mut self, v: i8) -> Self {
+        let level = self.default_level.as_usize() as isize + v as isize;
+        let max = FilterLevel::max().as_usize() as isize;
+        let level = if level < 0 {
+            0
+        } else if level > max {
+            max
+        } else {
+            level
+        };
+        self.default_level = FilterLevel::from_usize(level as usize).unwrap_or(self.default_level);
+        self
+    }
+
+    /// Remember the directive lookup per module, on by default
+    ///
+    /// Turning it off looks the directives up on every record, mostly useful
+    /// to measure the cache.
+    pub fn module_cache(mut self, enable: bool) -> Self {
+        self.module_cache = enable;
+        self
     }
 
     /// Adds filters to the logger
@@ -113,6 +233,8 @@ impl<T: Drain> LogBuilder<T> {
         self.directives.push(LogDirective {
             name: module.map(|s| s.to_string()),
             level: level,
+            filter: None,
+            preds: Vec::new(),
         });
         self
     }
@@ -122,9 +244,7 @@ impl<T: Drain> LogBuilder<T> {
     ///
     /// See the module documentation for more details.
     pub fn parse(mut self, filters: &str) -> Self {
-        let (directives, filter) = parse_logging_spec(filters);
-
-        self.filter = filter;
+        let directives = parse_logging_spec(filters);
 
         for directive in directives {
             self.directives.push(directive);
@@ -132,66 +252,93 @@ impl<T: Drain> LogBuilder<T> {
         self
     }
 
-    /// Build an env logger.
-    pub fn build(mut self) -> EnvDrain<T> {
-        if self.directives.is_empty() {
-            // Adds the default filter if none exist
-            self.directives.push(LogDirective {
-                name: None,
-                level: FilterLevel::Error,
-            });
-        } else {
-            // Sort the directives by length of their name, this allows a
-            // little more efficient lookup at runtime.
-            self.directives.sort_by(|a, b| {
-                let alen = a.name.as_ref().map(|a| a.len()).unwrap_or(0);
-                let blen = b.name.as_ref().map(|b| b.len()).unwrap_or(0);
-                alen.cmp(&blen)
-            });
+    /// Parses the directives string like `parse`, but fails on the first
+    /// invalid directive instead of ignoring it.
+    pub fn try_parse(mut self, filters: &str) -> Result<Self, SpecError> {
+        let (directives, mut errors) = parse_spec(filters);
+        if !errors.is_empty() {
+            return Err(errors.remove(0));
         }
 
+        for directive in directives {
+            self.directives.push(directive);
+        }
+        Ok(self)
+    }
+
+    /// Build an env logger.
+    pub fn build(self) -> EnvDrain<T> {
         let LogBuilder {
             drain,
             directives,
-            filter,
+            default_level,
+            module_cache,
+            ..
         } = self;
+        let rules = Rules::new(directives, default_level);
 
         EnvDrain {
             drain: drain,
-            directives: directives,
-            filter: filter,
+            rules: Arc::new(RulesCell::new(rules)),
+            module_cache: module_cache,
         }
     }
+
+    /// Build an env logger and a `FilterHandle` to change its rules at runtime.
+    pub fn build_with_handle(self) -> (EnvDrain<T>, FilterHandle) {
+        let default_level = self.default_level;
+        let drain = self.build();
+        let handle = FilterHandle {
+            rules: drain.rules.clone(),
+            writer: Arc::new(Mutex::new(())),
+            default_level: default_level,
+        };
+        (drain, handle)
+    }
 }
 
 impl<T: Drain> EnvDrain<T> {
     /// Create a `EnvDrain` using `RUST_LOG` environment variable
+    ///
+    /// See `LogBuilder::from_env` to use other variables.
     pub fn new(d: T) -> LogBuilder<T> {
-        let mut builder = LogBuilder::new(d);
+        LogBuilder::from_env(d, "RUST_LOG")
+    }
 
-        if let Ok(s) = env::var("RUST_LOG") {
-            builder = builder.parse(&s);
-        }
+    /// Create a `EnvDrain` using `RUST_LOG` environment variable, fails if
+    /// any of its directives is invalid.
+    pub fn new_strict(d: T) -> Result<LogBuilder<T>, SpecError> {
+        let builder = LogBuilder::new(d);
 
-        builder
+        match env::var("RUST_LOG") {
+            Ok(s) => builder.try_parse(&s),
+            Err(_) => Ok(builder),
+        }
     }
 
     /// return the env FilterLevel
     pub fn filter(&self) -> FilterLevel {
-        self.directives.iter().map(|d| d.level).max().unwrap_or(
-            FilterLevel::Off,
-        )
+        self.rules.with(|rules| rules.max_level())
     }
 
-    fn enabled(&self, level: slog::Level, module: &str) -> bool {
-        // Search for the longest match, the vector is assumed to be pre-sorted.
-        for directive in self.directives.iter().rev() {
-            match directive.name {
-                Some(ref name) if !module.starts_with(&**name) => {}
-                Some(..) | None => return level.as_usize() <= directive.level.as_usize(),
-            }
-        }
-        false
+    /// Check if the records of the level from the module could be logged
+    ///
+    /// The predicates and the regex filters depend on the record, a
+    /// directive with them counts as passing.
+    pub fn is_enabled(&self, level: slog::Level, module: &str) -> bool {
+        self.rules.with(|rules| rules.enabled(level, module))
+    }
+
+    /// Return the active directives, the most specific last
+    pub fn directives(&self) -> Vec<Directive> {
+        self.rules.with(|rules| rules.directives.iter().map(LogDirective::to_public).collect())
+    }
+}
+
+/// print the active rules in the `RUST_LOG` spec form
+impl<T: Drain> fmt::Display for EnvDrain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.rules.with(|rules| write!(f, "{}", rules))
     }
 }
 
@@ -202,118 +349,928 @@ where
     type Err = T::Err;
     type Ok = ();
     fn log(&self, info: &slog::Record, val: &slog::OwnedKVList) -> Result<(), T::Err> {
-        if !self.enabled(info.level(), info.module()) {
+        // decide with the rules borrowed, the wrapped drain may yield
+        let pass = self.rules.with(|rules| {
+            let directive = if self.module_cache {
+                rules.find_cached(info.module(), (info, val))
+            } else {
+                rules.find(info.module(), Some((info, val)))
+            };
+            let directive = match directive {
+                Some(d) if info.level().as_usize() <= d.level.as_usize() => d,
+                _ => return false,
+            };
+            match directive.filter.as_ref() {
+                Some(filter) => match_msg(filter, info.msg()),
+                None => true,
+            }
+        });
+
+        if !pass {
             return Ok(());
         }
+        self.drain.log(info, val)
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        let max_level = self.rules.with(|rules| rules.max_level());
+        level.as_usize() <= max_level.as_usize() && self.drain.is_enabled(level)
+    }
+
+    fn flush(&self) -> Result<(), slog::FlushError> {
+        self.drain.flush()
+    }
+}
+
+/// A directive of the logging spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    /// the module path, `None` for all the modules
+    pub module: Option<String>,
+    /// the max level let through
+    pub level: FilterLevel,
+    /// the regex the message must match
+    pub filter: Option<String>,
+    /// the predicates in the spec form, `key=value` or `@tag=audit`
+    pub predicates: Vec<String>,
+}
 
-        if let Some(filter) = self.filter.as_ref() {
-            if !filter.is_match(&format!("{}", info.msg())) {
-                return Ok(());
+
+/// Error of an invalid directive in the logging spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecError {
+    /// the offending directive
+    pub directive: String,
+    /// byte range of the directive in the spec
+    pub span: Range<usize>,
+    /// why the directive is invalid
+    pub reason: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid logging spec '{}' at {}..{}: {}",
+            self.directive,
+            self.span.start,
+            self.span.end,
+            self.reason
+        )
+    }
+}
+
+impl Error for SpecError {
+    fn description(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Handle to change the filtering rules of an `EnvDrain` at runtime
+///
+/// Created by `LogBuilder::build_with_handle`. The new rules are swapped in
+/// as a whole, logging reads them without a lock. The changes themselves are
+/// serialized, concurrent shifts all count.
+#[derive(Clone)]
+pub struct FilterHandle {
+    rules: Arc<RulesCell>,
+    /// held while the new rules are derived from the active ones
+    writer: Arc<Mutex<()>>,
+    default_level: FilterLevel,
+}
+
+impl FilterHandle {
+    /// Parse the spec (in the same form as `RUST_LOG`) and make it active
+    ///
+    /// The active rules are kept if the spec is invalid.
+    pub fn set_spec(&self, spec: &str) -> Result<(), SpecError> {
+        let (directives, mut errors) = parse_spec(spec);
+        if !errors.is_empty() {
+            return Err(errors.remove(0));
+        }
+        let _writer = self.lock_writer();
+        self.rules.set(Rules::new(directives, self.default_level));
+        Ok(())
+    }
+
+    /// Return the active spec
+    pub fn current_spec(&self) -> String {
+        self.rules.with(|rules| rules.to_string())
+    }
+
+    /// Shift the level of every directive by `v` steps
+    ///
+    /// The shifts add up and are relative to the spec, so raising and then
+    /// lowering comes back to the same rules, `set_spec` resets them. The
+    /// `off` directives are kept and the other ones don't go below
+    /// `critical`. Return the new max level.
+    pub fn shift_verbosity(&self, v: i8) -> FilterLevel {
+        let _writer = self.lock_writer();
+        let rules = self.rules.with(|rules| rules.with_verbosity(rules.verbosity + v as isize));
+        let level = rules.max_level();
+        self.rules.set(rules);
+        level
+    }
+
+    /// Check if the level is let through by any of the directives
+    pub fn is_enabled(&self, level: slog::Level) -> bool {
+        level.as_usize() <= self.rules.with(|rules| rules.max_level()).as_usize()
+    }
+
+    /// Return the sum of the shifts done by `shift_verbosity`
+    pub fn verbosity(&self) -> isize {
+        self.rules.with(|rules| rules.verbosity)
+    }
+
+    fn lock_writer(&self) -> MutexGuard<()> {
+        // the guarded data is `()`, a poisoned lock is still usable
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// lock-free cell of the active rules
+///
+/// Readers pin the crossbeam epoch and borrow the rules in place, no lock
+/// and no reference count is taken. Replaced rules are freed once no pinned
+/// reader can see them any more.
+struct RulesCell {
+    rules: Atomic<Rules>,
+}
+
+impl RulesCell {
+    fn new(rules: Rules) -> Self {
+        RulesCell { rules: Atomic::new(rules) }
+    }
+
+    /// run `f` with the active rules
+    ///
+    /// The epoch is pinned per thread, `f` must not yield the coroutine.
+    fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Rules) -> R,
+    {
+        let guard = epoch::pin();
+        let rules = self.rules.load(Ordering::Acquire, &guard).expect(
+            "the rules are set until drop",
+        );
+        f(&rules)
+    }
+
+    fn set(&self, rules: Rules) {
+        let guard = epoch::pin();
+        if let Some(old) = self.rules.swap(Some(Owned::new(rules)), Ordering::AcqRel, &guard) {
+            unsafe { guard.unlinked(old) }
+        }
+    }
+}
+
+impl Drop for RulesCell {
+    fn drop(&mut self) {
+        let guard = epoch::pin();
+        if let Some(old) = self.rules.swap(None, Ordering::AcqRel, &guard) {
+            unsafe { guard.unlinked(old) }
+        }
+    }
+}
+
+/// the filtering rules, replaced as a whole when changed
+///
+/// The module cache lives with the rules, so replacing the rules also
+/// invalidates the cached decisions.
+struct Rules {
+    directives: Vec<LogDirective>,
+    cache: ModuleCache,
+    /// the directives as parsed, before the verbosity shift
+    base: Vec<LogDirective>,
+    verbosity: isize,
+}
+
+impl Rules {
+    fn new(mut directives: Vec<LogDirective>, default_level: FilterLevel) -> Self {
+        if directives.is_empty() {
+            // Adds the default filter if none exist
+            directives.push(LogDirective {
+                name: None,
+                level: default_level,
+                filter: None,
+                preds: Vec::new(),
+            });
+        } else {
+            // Sort the directives from the least to the most specific path,
+            // the lookup takes the last match. Directives with predicates
+            // come after all the plain ones, so they are checked first. The
+            // sort is stable, the later of equal directives wins.
+            directives.sort_by(|a, b| {
+                let akey = (!a.preds.is_empty(), a.rank(), a.preds.len());
+                akey.cmp(&(!b.preds.is_empty(), b.rank(), b.preds.len()))
+            });
+        }
+
+        Rules {
+            base: directives.clone(),
+            directives: directives,
+            cache: ModuleCache::new(),
+            verbosity: 0,
+        }
+    }
+
+    /// the rules with the levels of the spec shifted by `verbosity` steps
+    fn with_verbosity(&self, verbosity: isize) -> Rules {
+        let directives = self.base
+            .iter()
+            .map(|d| {
+                let mut d = d.clone();
+                d.level = shift_level(d.level, verbosity);
+                d
+            })
+            .collect();
+        Rules {
+            directives: directives,
+            cache: ModuleCache::new(),
+            base: self.base.clone(),
+            verbosity: verbosity,
+        }
+    }
+
+    fn max_level(&self) -> FilterLevel {
+        self.directives.iter().map(|d| d.level).max().unwrap_or(
+            FilterLevel::Off,
+        )
+    }
+
+    /// return the directive that applies to the module
+    ///
+    /// Directives with predicates are only considered when the record and the
+    /// logger values are given.
+    fn find(
+        &self,
+        module: &str,
+        record: Option<(&slog::Record, &slog::OwnedKVList)>,
+    ) -> Option<&LogDirective> {
+        // Search for the most specific match, the vector is assumed to be pre-sorted.
+        for directive in self.directives.iter().rev() {
+            if !directive.match_module(module) {
+                continue;
+            }
+            if directive.preds.is_empty() {
+                return Some(directive);
+            }
+            match record {
+                Some((record, values)) if directive.match_preds(record, values) => {
+                    return Some(directive)
+                }
+                _ => {}
             }
         }
+        None
+    }
 
-        self.drain.log(info, val)
+    /// same as `find`, but the module lookup is memoized per module string
+    ///
+    /// Only the record's `&'static str` module is used as the key, so an
+    /// address can't be reused by a different module.
+    fn find_cached(
+        &self,
+        module: &'static str,
+        record: (&slog::Record, &slog::OwnedKVList),
+    ) -> Option<&LogDirective> {
+        let key = (module.as_ptr() as usize, module.len());
+        let m = match self.cache.get(key) {
+            Some(m) => m,
+            None => {
+                let m = self.match_module(module);
+                self.cache.insert(key, m);
+                m
+            }
+        };
+        if m.has_preds {
+            // the decision depends on the record
+            return self.find(module, Some(record));
+        }
+        m.plain.map(|i| &self.directives[i])
+    }
+
+    /// the record independent part of `find`
+    fn match_module(&self, module: &str) -> ModuleMatch {
+        let mut has_preds = false;
+        for (i, directive) in self.directives.iter().enumerate().rev() {
+            if !directive.match_module(module) {
+                continue;
+            }
+            if directive.preds.is_empty() {
+                return ModuleMatch {
+                    plain: Some(i),
+                    has_preds: has_preds,
+                };
+            }
+            has_preds = true;
+        }
+        ModuleMatch {
+            plain: None,
+            has_preds: has_preds,
+        }
+    }
+
+    fn enabled(&self, level: slog::Level, module: &str) -> bool {
+        for directive in self.directives.iter().rev() {
+            if !directive.match_module(module) {
+                continue;
+            }
+            let enabled = level.as_usize() <= directive.level.as_usize();
+            if directive.preds.is_empty() || enabled {
+                return enabled;
+            }
+        }
+        false
+    }
+}
+
+fn shift_level(level: FilterLevel, v: isize) -> FilterLevel {
+    if level == FilterLevel::Off {
+        return level;
+    }
+    let min = FilterLevel::Critical.as_usize() as isize;
+    let max = FilterLevel::max().as_usize() as isize;
+    let shifted = level.as_usize() as isize + v;
+    let shifted = if shifted < min {
+        min
+    } else if shifted > max {
+        max
+    } else {
+        shifted
+    };
+    FilterLevel::from_usize(shifted as usize).unwrap_or(level)
+}
+
+/// number of the module cache slots
+const MODULE_CACHE_SIZE: usize = 1024;
+
+/// slots tried for a module before it's left out of the cache
+const MODULE_CACHE_PROBES: usize = 16;
+
+/// cached directive lookup result of a module
+#[derive(Debug, Clone, Copy)]
+struct ModuleMatch {
+    /// index of the most specific matching directive without predicates
+    plain: Option<usize>,
+    /// is there a matching directive with predicates
+    has_preds: bool,
+}
+
+impl ModuleMatch {
+    /// pack into a slot value, the lowest bit marks it as written
+    fn encode(&self) -> usize {
+        let plain = self.plain.map_or(0, |i| i + 1);
+        plain << 2 | (self.has_preds as usize) << 1 | 1
     }
+
+    fn decode(value: usize) -> Self {
+        ModuleMatch {
+            plain: match value >> 2 {
+                0 => None,
+                i => Some(i - 1),
+            },
+            has_preds: value & 2 != 0,
+        }
+    }
+}
+
+struct ModuleSlot {
+    /// address of the module string, 0 for a free slot
+    ptr: AtomicUsize,
+    len: AtomicUsize,
+    /// the encoded `ModuleMatch`, 0 until it's written
+    value: AtomicUsize,
 }
 
+/// lock-free table of the module lookups
+///
+/// A slot is claimed once by a CAS of the module address and never reused,
+/// a read is a few atomic loads. A module which doesn't get a slot within
+/// `MODULE_CACHE_PROBES` steps is looked up on every record.
+struct ModuleCache {
+    slots: Box<[ModuleSlot]>,
+}
+
+impl ModuleCache {
+    fn new() -> Self {
+        let slots: Vec<_> = (0..MODULE_CACHE_SIZE)
+            .map(|_| {
+                ModuleSlot {
+                    ptr: AtomicUsize::new(0),
+                    len: AtomicUsize::new(0),
+                    value: AtomicUsize::new(0),
+                }
+            })
+            .collect();
+        ModuleCache { slots: slots.into_boxed_slice() }
+    }
+
+    fn slot(&self, key: (usize, usize), probe: usize) -> &ModuleSlot {
+        let hash = (key.0 ^ key.1.rotate_left(16)).wrapping_mul(0x9E37_79B9) >> 8;
+        &self.slots[(hash + probe) % MODULE_CACHE_SIZE]
+    }
+
+    fn get(&self, key: (usize, usize)) -> Option<ModuleMatch> {
+        for probe in 0..MODULE_CACHE_PROBES {
+            let slot = self.slot(key, probe);
+            match slot.ptr.load(Ordering::Acquire) {
+                0 => return None,
+                ptr if ptr != key.0 => continue,
+                _ => {}
+            }
+            let value = slot.value.load(Ordering::Acquire);
+            if value == 0 {
+                // still being written
+                return None;
+            }
+            if slot.len.load(Ordering::Relaxed) == key.1 {
+                return Some(ModuleMatch::decode(value));
+            }
+        }
+        None
+    }
+
+    fn insert(&self, key: (usize, usize), m: ModuleMatch) {
+        for probe in 0..MODULE_CACHE_PROBES {
+            let slot = self.slot(key, probe);
+            // a concurrent insert of the same key may take a second slot,
+            // the first one is found
+            if slot.ptr.compare_and_swap(0, key.0, Ordering::AcqRel) == 0 {
+                slot.len.store(key.1, Ordering::Relaxed);
+                slot.value.store(m.encode(), Ordering::Release);
+                return;
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// reused buffer for formatting the messages checked by the regex filters
+    static MSG_BUF: RefCell<String> = RefCell::new(String::new());
+}
+
+/// match the formatted message against the filter without allocating
+fn match_msg(filter: &Regex, msg: &fmt::Arguments) -> bool {
+    MSG_BUF.with(|buf| match buf.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            let _ = buf.write_fmt(*msg);
+            let matched = filter.is_match(&buf);
+            if buf.capacity() > 4096 {
+                // don't hold on to a huge message
+                *buf = String::new();
+            }
+            matched
+        }
+        // the message formatting is logging itself
+        Err(_) => filter.is_match(&format!("{}", msg)),
+    })
+}
+
+/// print the rules in the `RUST_LOG` spec form
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, directive) in self.directives.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", directive)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 struct LogDirective {
     name: Option<String>,
     level: FilterLevel,
+    filter: Option<Regex>,
+    preds: Vec<Predicate>,
+}
+
+impl LogDirective {
+    fn to_public(&self) -> Directive {
+        Directive {
+            module: self.name.clone(),
+            level: self.level,
+            filter: self.filter.as_ref().map(|f| f.as_str().to_owned()),
+            predicates: self.preds.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// how specific the path is: the number of segments, of the segments
+    /// without globs and the length
+    fn rank(&self) -> (usize, usize, usize) {
+        let name = match self.name {
+            Some(ref name) => name,
+            None => return (0, 0, 0),
+        };
+        let segments = name.split("::").count();
+        let literal = name.split("::")
+            .filter(|s| !s.contains(|c: char| c == '*' || c == '?'))
+            .count();
+        (segments, literal, name.len())
+    }
+
+    /// does the directive apply to the module
+    ///
+    /// The name matches the module or one of its parents, so `foo` matches
+    /// `foo::bar` but not `foobar`.
+    fn match_module(&self, module: &str) -> bool {
+        let name = match self.name {
+            Some(ref name) => name,
+            None => return true,
+        };
+        if !name.contains(|c: char| c == '*' || c == '?') {
+            return module.starts_with(&**name) &&
+                (module.len() == name.len() || module[name.len()..].starts_with("::"));
+        }
+        // try the module and each of its parents
+        let mut end = module.len();
+        loop {
+            if glob_match(name, &module[..end]) {
+                return true;
+            }
+            match module[..end].rfind("::") {
+                Some(pos) => end = pos,
+                None => return false,
+            }
+        }
+    }
+
+    /// check the predicates against the record and logger values
+    fn match_preds(&self, record: &slog::Record, values: &slog::OwnedKVList) -> bool {
+        let mut has_kv = false;
+        for pred in &self.preds {
+            let matched = match *pred {
+                Predicate::KV(..) => {
+                    has_kv = true;
+                    true
+                }
+                Predicate::Tag(ref tag) => record.tag() == tag,
+                Predicate::File(ref glob) => glob_match(glob, record.file()),
+                Predicate::Line(from, to) => from <= record.line() && record.line() <= to,
+            };
+            if !matched {
+                return false;
+            }
+        }
+        if !has_kv {
+            return true;
+        }
+
+        let mut matcher = KVMatcher {
+            preds: &self.preds,
+            matched: vec![None; self.preds.len()],
+            buf: String::new(),
+        };
+        let _ = record.kv().serialize(record, &mut matcher);
+        let _ = values.serialize(record, &mut matcher);
+        self.preds.iter().zip(matcher.matched).all(|(pred, m)| match *pred {
+            Predicate::KV(..) => m == Some(true),
+            _ => true,
+        })
+    }
+}
+
+/// selector of a directive, in the `[]` after the module path
+#[derive(Debug, Clone, PartialEq)]
+enum Predicate {
+    /// `key=value`, a record or logger key-value
+    KV(String, String),
+    /// `@tag=tag`, the record tag
+    Tag(String),
+    /// `@file=glob`, the record source file
+    File(String),
+    /// `@line=from-to`, inclusive range of the record source line
+    Line(u32, u32),
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Predicate::KV(ref k, ref v) => write!(f, "{}={}", k, v),
+            Predicate::Tag(ref tag) => write!(f, "@tag={}", tag),
+            Predicate::File(ref glob) => write!(f, "@file={}", glob),
+            Predicate::Line(from, to) if from == to => write!(f, "@line={}", from),
+            Predicate::Line(from, to) => {
+                write!(f, "@line=")?;
+                if from != 0 {
+                    write!(f, "{}", from)?;
+                }
+                write!(f, "-")?;
+                if to != u32::max_value() {
+                    write!(f, "{}", to)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// match `s` against a glob, `*` matches any chars and `?` a single char
+fn glob_match(glob: &str, s: &str) -> bool {
+    let glob = glob.as_bytes();
+    let s = s.as_bytes();
+    let (mut g, mut i) = (0, 0);
+    // position of the last `*` and the input position it matched up to
+    let mut star = None;
+    while i < s.len() {
+        if g < glob.len() && (glob[g] == b'?' || glob[g] == s[i]) {
+            g += 1;
+            i += 1;
+        } else if g < glob.len() && glob[g] == b'*' {
+            star = Some((g, i));
+            g += 1;
+        } else if let Some((sg, si)) = star {
+            g = sg + 1;
+            i = si + 1;
+            star = Some((sg, si + 1));
+        } else {
+            return false;
+        }
+    }
+    glob[g..].iter().all(|c| *c == b'*')
+}
+
+impl fmt::Display for LogDirective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = self.level.as_str().to_lowercase();
+        if let Some(ref name) = self.name {
+            write!(f, "{}", name)?;
+        }
+        if !self.preds.is_empty() {
+            write!(f, "[")?;
+            for (i, pred) in self.preds.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", pred)?;
+            }
+            write!(f, "]")?;
+        }
+        if self.name.is_some() || !self.preds.is_empty() {
+            write!(f, "=")?;
+        }
+        write!(f, "{}", level)?;
+        if let Some(ref filter) = self.filter {
+            write!(f, "/{}", filter.as_str().replace(',', "\\,"))?;
+        }
+        Ok(())
+    }
+}
+
+/// `Serializer` that checks the key-value predicates against a record
+///
+/// Only the first value of a key counts, it's the one that overrides the
+/// others (record values first, then the newest logger values).
+struct KVMatcher<'a> {
+    preds: &'a [Predicate],
+    matched: Vec<Option<bool>>,
+    buf: String,
+}
+
+impl<'a> Serializer for KVMatcher<'a> {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        let mut formatted = false;
+        for (i, pred) in self.preds.iter().enumerate() {
+            let (k, v) = match *pred {
+                Predicate::KV(ref k, ref v) => (k, v),
+                _ => continue,
+            };
+            if k.as_str() != key || self.matched[i].is_some() {
+                continue;
+            }
+            if !formatted {
+                self.buf.clear();
+                let _ = write!(self.buf, "{}", val);
+                formatted = true;
+            }
+            self.matched[i] = Some(self.buf == *v);
+        }
+        Ok(())
+    }
 }
 
 /// Create a `EnvDrain` using `RUST_LOG` environment variable and log to stderr
-pub(crate) fn stderr_logger() -> slog::Logger {
+pub(crate) fn stderr_logger() -> (slog::Logger, FilterHandle) {
     let decrator = slog_term::TermDecorator::new().stderr().build();
     let drain = slog_term::CompactFormat::new(decrator).build();
-    let drain = EnvDrain::new(drain.fuse()).build();
+    // the filter runs before the lock, only a written record takes it, once
     let drain = MutexDrain::new(drain.fuse());
+    let (drain, handle) = EnvDrain::new(drain).build_with_handle();
+
+    (slog::Logger::root(drain.fuse(), o!()).into_erased(), handle)
+}
 
-    slog::Logger::root(drain.fuse(), o!()).into_erased()
+/// Split the spec on `,`, a `\,` is kept as a literal `,` of the directive.
+/// The `,` between the `[]` of the key-value predicates doesn't split.
+///
+/// Return the directives with their byte range in the spec.
+fn split_directives(spec: &str) -> Vec<(Range<usize>, String)> {
+    let mut dirs = Vec::new();
+    let mut cur = String::new();
+    let mut start = 0;
+    let mut in_preds = false;
+    let mut in_filter = false;
+    let mut chars = spec.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if chars.peek().map(|&(_, c)| c) == Some(',') => {
+                cur.push(',');
+                chars.next();
+            }
+            ',' if !in_preds => {
+                dirs.push((start..i, ::std::mem::replace(&mut cur, String::new())));
+                start = i + 1;
+                in_filter = false;
+            }
+            c => {
+                match c {
+                    '[' if !in_filter => in_preds = true,
+                    ']' if !in_filter => in_preds = false,
+                    '/' if !in_preds => in_filter = true,
+                    _ => {}
+                }
+                cur.push(c);
+            }
+        }
+    }
+    dirs.push((start..spec.len(), cur));
+    dirs
 }
 
 /// Parse a logging specification string (e.g: "crate1,crate2::mod3,crate3::x=error/foo")
 /// and return a vector with log directives.
-fn parse_logging_spec(spec: &str) -> (Vec<LogDirective>, Option<Regex>) {
+///
+/// The invalid directives are reported on stderr and ignored.
+fn parse_logging_spec(spec: &str) -> Vec<LogDirective> {
+    let (dirs, errors) = parse_spec(spec);
+    for e in errors {
+        eprintln!("warning: {}, ignoring it", e);
+    }
+    dirs
+}
+
+/// Parse a logging specification string, return the valid directives and
+/// the errors of the invalid ones.
+fn parse_spec(spec: &str) -> (Vec<LogDirective>, Vec<SpecError>) {
     let mut dirs = Vec::new();
+    let mut errors = Vec::new();
 
-    let mut parts = spec.split('/');
-    let mods = parts.next();
-    let filter = parts.next();
-    if parts.next().is_some() {
-        println!(
-            "warning: invalid logging spec '{}', \
-                 ignoring it (too many '/'s)",
-            spec
-        );
-        return (dirs, None);
-    }
-    mods.map(|m| {
-        for s in m.split(',') {
-            if s.len() == 0 {
-                continue;
+    for (span, s) in split_directives(spec) {
+        if s.len() == 0 {
+            continue;
+        }
+        match parse_directive(&s) {
+            Ok(directive) => dirs.push(directive),
+            Err(reason) => {
+                errors.push(SpecError {
+                    directive: s.clone(),
+                    span: span,
+                    reason: reason,
+                })
             }
-            let mut parts = s.split('=');
-            let (log_level, name) =
-                match (parts.next(), parts.next().map(|s| s.trim()), parts.next()) {
-                    (Some(part0), None, None) => {
-                        // if the single argument is a log-level string or number,
-                        // treat that as a global fallback
-                        match part0.parse() {
-                            Ok(num) => (num, None),
-                            Err(_) => (FilterLevel::max(), Some(part0)),
-                        }
-                    }
-                    (Some(part0), Some(""), None) => (FilterLevel::max(), Some(part0)),
-                    (Some(part0), Some(part1), None) => {
-                        match part1.parse() {
-                            Ok(num) => (num, Some(part0)),
-                            _ => {
-                                println!(
-                                    "warning: invalid logging spec '{}', \
-                                 ignoring it",
-                                    part1
-                                );
-                                continue;
-                            }
-                        }
-                    }
-                    _ => {
-                        println!(
-                            "warning: invalid logging spec '{}', \
-                         ignoring it",
-                            s
-                        );
-                        continue;
-                    }
-                };
-            dirs.push(LogDirective {
-                name: name.map(|s| s.to_string()),
-                level: log_level,
-            });
         }
-    });
+    }
+
+    (dirs, errors)
+}
+
+/// Parse a single directive, return the reason if it's invalid
+fn parse_directive(s: &str) -> Result<LogDirective, String> {
+    // a `/` inside the key-value predicates doesn't start the filter
+    let filter_from = match (s.find('['), s.find('/')) {
+        (Some(open), Some(slash)) if open < slash => s.find(']').unwrap_or(0),
+        _ => 0,
+    };
+    let (s, filter) = match s[filter_from..].find('/') {
+        Some(pos) => (&s[..filter_from + pos], Some(&s[filter_from + pos + 1..])),
+        None => (&s[..], None),
+    };
+    // take out the predicates, `name[k=v,..]=level`
+    let (s, preds) = match (s.find('['), s.find(']')) {
+        (Some(open), Some(close)) if open < close => {
+            let preds = match parse_predicates(&s[open + 1..close]) {
+                Some(preds) => preds,
+                None => return Err(format!("invalid predicates '{}'", &s[open..close + 1])),
+            };
+            (format!("{}{}", &s[..open], &s[close + 1..]), preds)
+        }
+        _ => (s.to_owned(), Vec::new()),
+    };
+    // `-name` excludes the module, same as `name=off`
+    let s = if s.starts_with('-') {
+        if s.len() == 1 || s.contains('=') {
+            return Err(format!("invalid exclude directive '{}'", s));
+        }
+        format!("{}=off", &s[1..])
+    } else {
+        s
+    };
+    let mut parts = s.split('=');
+    let (log_level, name) =
+        match (parts.next(), parts.next().map(|s| s.trim()), parts.next()) {
+            (Some(part0), None, None) => {
+                // if the single argument is a log-level string or number,
+                // treat that as a global fallback
+                match part0.parse() {
+                    Ok(num) => (num, None),
+                    Err(_) => (FilterLevel::max(), Some(part0)),
+                }
+            }
+            (Some(part0), Some(""), None) => (FilterLevel::max(), Some(part0)),
+            (Some(part0), Some(part1), None) => {
+                match part1.parse() {
+                    Ok(num) => (num, Some(part0)),
+                    _ => return Err(format!("invalid log level '{}'", part1)),
+                }
+            }
+            _ => return Err("too many '='s".to_owned()),
+        };
 
-    let filter = filter.map_or(None, |filter| match Regex::new(filter) {
-        Ok(re) => Some(re),
-        Err(e) => {
-            println!("warning: invalid regex filter - {}", e);
-            None
+    let filter = match filter {
+        Some(filter) => {
+            match Regex::new(filter) {
+                Ok(re) => Some(re),
+                Err(e) => return Err(format!("invalid regex filter - {}", e)),
+            }
         }
-    });
+        None => None,
+    };
 
-    return (dirs, filter);
+    Ok(LogDirective {
+        name: name.and_then(|s| if s.is_empty() { None } else { Some(s.to_string()) }),
+        level: log_level,
+        filter: filter,
+        preds: preds,
+    })
+}
+
+/// Parse the predicates, `k1=v1,@tag=audit,@file=src/*.rs,@line=10-20`
+fn parse_predicates(spec: &str) -> Option<Vec<Predicate>> {
+    let mut preds = Vec::new();
+    for pred in spec.split(',') {
+        let mut parts = pred.splitn(2, '=');
+        let (k, v) = match (parts.next().map(|s| s.trim()), parts.next()) {
+            (Some(k), Some(v)) if !k.is_empty() => (k, v.trim()),
+            _ => return None,
+        };
+        preds.push(match k {
+            "@tag" => Predicate::Tag(v.to_owned()),
+            "@file" => Predicate::File(v.to_owned()),
+            "@line" => {
+                let (from, to) = parse_line_range(v)?;
+                Predicate::Line(from, to)
+            }
+            k if k.starts_with('@') => return None,
+            k => Predicate::KV(k.to_owned(), v.to_owned()),
+        });
+    }
+    Some(preds)
+}
+
+/// Parse a line range, `10`, `10-20`, `10-` or `-20`
+fn parse_line_range(spec: &str) -> Option<(u32, u32)> {
+    let mut parts = spec.splitn(2, '-');
+    let from = parts.next().unwrap_or("").trim();
+    let to = match parts.next() {
+        Some(to) => to.trim(),
+        None => return from.parse().ok().map(|l| (l, l)),
+    };
+    let from = if from.is_empty() { 0 } else { from.parse().ok()? };
+    let to = if to.is_empty() {
+        u32::max_value()
+    } else {
+        to.parse().ok()?
+    };
+    if from > to {
+        return None;
+    }
+    Some((from, to))
 }
 
 #[cfg(test)]
 mod tests {
-    use slog::{Level, FilterLevel};
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use slog::{Level, FilterLevel, Drain};
     use super::slog;
 
-    use super::{LogBuilder, EnvDrain, LogDirective, parse_logging_spec};
+    use super::{LogBuilder, EnvDrain, Directive, LogDirective, ModuleCache, Predicate, Rules,
+                SpecError, parse_logging_spec, glob_match};
+    use test_drains::Counter;
 
     fn make_logger(dirs: Vec<LogDirective>) -> EnvDrain<slog::Discard> {
-        let mut logger = LogBuilder::new(slog::Discard).build();
-        logger.directives = dirs;
+        let logger = LogBuilder::new(slog::Discard).build();
+        logger.rules.set(Rules {
+            base: Vec::new(),
+            directives: dirs,
+            cache: ModuleCache::new(),
+            verbosity: 0,
+        });
         logger
     }
 
@@ -322,8 +1279,8 @@ mod tests {
         let logger = LogBuilder::new(slog::Discard)
             .filter(None, FilterLevel::Info)
             .build();
-        assert!(logger.enabled(Level::Info, "crate1"));
-        assert!(!logger.enabled(Level::Debug, "crate1"));
+        assert!(logger.is_enabled(Level::Info, "crate1"));
+        assert!(!logger.is_enabled(Level::Debug, "crate1"));
     }
 
     #[test]
@@ -333,8 +1290,9 @@ mod tests {
             .filter(Some("crate2::mod"), FilterLevel::Debug)
             .filter(Some("crate1::mod1"), FilterLevel::Warning)
             .build();
-        assert!(logger.enabled(Level::Debug, "crate2::mod1"));
-        assert!(!logger.enabled(Level::Debug, "crate2"));
+        assert!(logger.is_enabled(Level::Debug, "crate2::mod::mod1"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2::mod1"));
     }
 
     #[test]
@@ -342,8 +1300,22 @@ mod tests {
         let logger = LogBuilder::new(slog::Discard)
             .parse("info,crate1::mod1=warn")
             .build();
-        assert!(logger.enabled(Level::Warning, "crate1::mod1"));
-        assert!(logger.enabled(Level::Info, "crate2::mod2"));
+        assert!(logger.is_enabled(Level::Warning, "crate1::mod1"));
+        assert!(logger.is_enabled(Level::Info, "crate2::mod2"));
+    }
+
+    #[test]
+    fn handle_set_spec() {
+        let (logger, handle) = LogBuilder::new(slog::Discard)
+            .parse("info")
+            .build_with_handle();
+        assert!(!logger.is_enabled(Level::Debug, "crate1::db"));
+
+        handle.set_spec("info,crate1::db=trace").unwrap();
+        assert!(logger.is_enabled(Level::Trace, "crate1::db"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2"));
+        assert_eq!(handle.current_spec(), "info,crate1::db=trace");
+        assert_eq!(logger.filter(), FilterLevel::Trace);
     }
 
     #[test]
@@ -352,16 +1324,20 @@ mod tests {
             LogDirective {
                 name: Some("crate2".to_string()),
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Warning,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(logger.enabled(Level::Warning, "crate1::mod1"));
-        assert!(!logger.enabled(Level::Info, "crate1::mod1"));
-        assert!(logger.enabled(Level::Info, "crate2"));
-        assert!(!logger.enabled(Level::Debug, "crate2"));
+        assert!(logger.is_enabled(Level::Warning, "crate1::mod1"));
+        assert!(!logger.is_enabled(Level::Info, "crate1::mod1"));
+        assert!(logger.is_enabled(Level::Info, "crate2"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2"));
     }
 
     #[test]
@@ -370,13 +1346,17 @@ mod tests {
             LogDirective {
                 name: Some("crate2".to_string()),
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Warning,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(!logger.enabled(Level::Warning, "crate3"));
+        assert!(!logger.is_enabled(Level::Warning, "crate3"));
     }
 
     #[test]
@@ -385,13 +1365,17 @@ mod tests {
             LogDirective {
                 name: Some("crate2".to_string()),
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Warning,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(logger.enabled(Level::Info, "crate2::mod1"));
+        assert!(logger.is_enabled(Level::Info, "crate2::mod1"));
     }
 
     #[test]
@@ -400,18 +1384,25 @@ mod tests {
             LogDirective {
                 name: Some("crate2".to_string()),
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate2::mod".to_string()),
                 level: FilterLevel::Debug,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Warning,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(logger.enabled(Level::Debug, "crate2::mod1"));
-        assert!(!logger.enabled(Level::Debug, "crate2"));
+        assert!(logger.is_enabled(Level::Debug, "crate2::mod::mod1"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2::mod1"));
     }
 
     #[test]
@@ -420,14 +1411,18 @@ mod tests {
             LogDirective {
                 name: None,
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Warning,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(logger.enabled(Level::Warning, "crate1::mod1"));
-        assert!(logger.enabled(Level::Info, "crate2::mod2"));
+        assert!(logger.is_enabled(Level::Warning, "crate1::mod1"));
+        assert!(logger.is_enabled(Level::Info, "crate2::mod2"));
     }
 
     #[test]
@@ -436,19 +1431,23 @@ mod tests {
             LogDirective {
                 name: None,
                 level: FilterLevel::Info,
+                filter: None,
+                preds: Vec::new(),
             },
             LogDirective {
                 name: Some("crate1::mod1".to_string()),
                 level: FilterLevel::Off,
+                filter: None,
+                preds: Vec::new(),
             },
         ]);
-        assert!(!logger.enabled(Level::Error, "crate1::mod1"));
-        assert!(logger.enabled(Level::Info, "crate2::mod2"));
+        assert!(!logger.is_enabled(Level::Error, "crate1::mod1"));
+        assert!(logger.is_enabled(Level::Info, "crate2::mod2"));
     }
 
     #[test]
     fn parse_logging_spec_valid() {
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=error,crate1::mod2,crate2=debug");
+        let dirs = parse_logging_spec("crate1::mod1=error,crate1::mod2,crate2=debug");
         assert_eq!(dirs.len(), 3);
         assert_eq!(dirs[0].name, Some("crate1::mod1".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Error);
@@ -458,64 +1457,64 @@ mod tests {
 
         assert_eq!(dirs[2].name, Some("crate2".to_string()));
         assert_eq!(dirs[2].level, FilterLevel::Debug);
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_invalid_crate() {
         // test parse_logging_spec with multiple = in specification
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=warn=info,crate2=debug");
+        let dirs = parse_logging_spec("crate1::mod1=warn=info,crate2=debug");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate2".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Debug);
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_invalid_log_level() {
         // test parse_logging_spec with 'noNumber' as log level
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=noNumber,crate2=debug");
+        let dirs = parse_logging_spec("crate1::mod1=noNumber,crate2=debug");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate2".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Debug);
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_string_log_level() {
         // test parse_logging_spec with 'warn' as log level
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=wrong,crate2=warn");
+        let dirs = parse_logging_spec("crate1::mod1=wrong,crate2=warn");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate2".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Warning);
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_empty_log_level() {
         // test parse_logging_spec with '' as log level
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=wrong,crate2=");
+        let dirs = parse_logging_spec("crate1::mod1=wrong,crate2=");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate2".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::max());
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_global() {
         // test parse_logging_spec with no crate
-        let (dirs, filter) = parse_logging_spec("warn,crate2=debug");
+        let dirs = parse_logging_spec("warn,crate2=debug");
         assert_eq!(dirs.len(), 2);
         assert_eq!(dirs[0].name, None);
         assert_eq!(dirs[0].level, FilterLevel::Warning);
         assert_eq!(dirs[1].name, Some("crate2".to_string()));
         assert_eq!(dirs[1].level, FilterLevel::Debug);
-        assert!(filter.is_none());
+        assert!(dirs.iter().all(|d| d.filter.is_none()));
     }
 
     #[test]
     fn parse_logging_spec_valid_filter() {
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=error,crate1::mod2,crate2=debug/abc");
+        let dirs = parse_logging_spec("crate1::mod1=error,crate1::mod2,crate2=debug/abc");
         assert_eq!(dirs.len(), 3);
         assert_eq!(dirs[0].name, Some("crate1::mod1".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Error);
@@ -525,24 +1524,419 @@ mod tests {
 
         assert_eq!(dirs[2].name, Some("crate2".to_string()));
         assert_eq!(dirs[2].level, FilterLevel::Debug);
-        assert!(filter.is_some() && filter.unwrap().to_string() == "abc");
+        assert!(dirs[0].filter.is_none() && dirs[1].filter.is_none());
+        assert!(dirs[2].filter.as_ref().unwrap().to_string() == "abc");
     }
 
     #[test]
     fn parse_logging_spec_invalid_crate_filter() {
-        let (dirs, filter) = parse_logging_spec("crate1::mod1=error=warn,crate2=debug/a.c");
+        let dirs = parse_logging_spec("crate1::mod1=error=warn,crate2=debug/a.c");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate2".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::Debug);
-        assert!(filter.is_some() && filter.unwrap().to_string() == "a.c");
+        assert!(dirs[0].filter.as_ref().unwrap().to_string() == "a.c");
     }
 
     #[test]
     fn parse_logging_spec_empty_with_filter() {
-        let (dirs, filter) = parse_logging_spec("crate1/a*c");
+        let dirs = parse_logging_spec("crate1/a*c");
         assert_eq!(dirs.len(), 1);
         assert_eq!(dirs[0].name, Some("crate1".to_string()));
         assert_eq!(dirs[0].level, FilterLevel::max());
-        assert!(filter.is_some() && filter.unwrap().to_string() == "a*c");
+        assert!(dirs[0].filter.as_ref().unwrap().to_string() == "a*c");
+    }
+
+    #[test]
+    fn parse_logging_spec_per_directive_filter() {
+        let dirs = parse_logging_spec("hello=debug/foo,world=info/bar");
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].name, Some("hello".to_string()));
+        assert_eq!(dirs[0].level, FilterLevel::Debug);
+        assert!(dirs[0].filter.as_ref().unwrap().to_string() == "foo");
+        assert_eq!(dirs[1].name, Some("world".to_string()));
+        assert_eq!(dirs[1].level, FilterLevel::Info);
+        assert!(dirs[1].filter.as_ref().unwrap().to_string() == "bar");
+    }
+
+    #[test]
+    fn parse_logging_spec_escaped_comma_filter() {
+        let dirs = parse_logging_spec(r"hello=debug/a\,b,world");
+        assert_eq!(dirs.len(), 2);
+        assert!(dirs[0].filter.as_ref().unwrap().to_string() == "a,b");
+        assert_eq!(dirs[1].name, Some("world".to_string()));
+        assert!(dirs[1].filter.is_none());
+
+        let (_, handle) = LogBuilder::new(slog::Discard).build_with_handle();
+        handle.set_spec(r"hello=debug/a\,b").unwrap();
+        assert_eq!(handle.current_spec(), r"hello=debug/a\,b");
+    }
+
+    #[test]
+    fn parse_logging_spec_kvs() {
+        let dirs = parse_logging_spec("app::http[peer_addr=8.8.8.8]=trace,[user_id=42,port=80]=debug");
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].name, Some("app::http".to_string()));
+        assert_eq!(dirs[0].level, FilterLevel::Trace);
+        assert_eq!(
+            dirs[0].preds,
+            vec![Predicate::KV("peer_addr".to_string(), "8.8.8.8".to_string())]
+        );
+        assert_eq!(dirs[1].name, None);
+        assert_eq!(dirs[1].level, FilterLevel::Debug);
+        assert_eq!(dirs[1].preds.len(), 2);
+
+        let dirs = parse_logging_spec("crate1[peer],crate2=debug");
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].name, Some("crate2".to_string()));
+    }
+
+    #[test]
+    fn match_kvs() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let drain = LogBuilder::new(Counter(count.clone()))
+            .parse("info,[peer_addr=8.8.8.8]=trace")
+            .build();
+        let logger = slog::Logger::root(drain.fuse(), o!("peer_addr" => "8.8.8.8"));
+
+        slog_trace!(logger, "from logger values");
+        slog_trace!(logger.new(o!("peer_addr" => "82.9.9.9")), "overridden");
+        slog_trace!(logger, "from record"; "user_id" => 42);
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+
+        let logger = slog::Logger::root(
+            LogBuilder::new(Counter(count.clone()))
+                .parse("info,[user_id=42]=debug")
+                .build()
+                .fuse(),
+            o!(),
+        );
+        slog_debug!(logger, "matched"; "user_id" => 42);
+        slog_debug!(logger, "not matched"; "user_id" => 43);
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn parse_logging_spec_meta() {
+        let dirs = parse_logging_spec("[@tag=slog-async]=off,app[@file=src/db/*.rs,@line=10-20]=debug");
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].name, None);
+        assert_eq!(dirs[0].level, FilterLevel::Off);
+        assert_eq!(dirs[0].preds, vec![Predicate::Tag("slog-async".to_string())]);
+        assert_eq!(dirs[1].name, Some("app".to_string()));
+        assert_eq!(
+            dirs[1].preds,
+            vec![
+                Predicate::File("src/db/*.rs".to_string()),
+                Predicate::Line(10, 20),
+            ]
+        );
+
+        let dirs = parse_logging_spec("app[@line=-20],app[@line=10-],app[@line=20-10],app[@foo=1]");
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].preds, vec![Predicate::Line(0, 20)]);
+        assert_eq!(dirs[1].preds, vec![Predicate::Line(10, u32::max_value())]);
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("src/db/*.rs", "src/db/query.rs"));
+        assert!(glob_match("*/db/*", "src/db/query.rs"));
+        assert!(glob_match("src/d?/query.rs", "src/db/query.rs"));
+        assert!(!glob_match("src/db/*.rs", "src/http/query.rs"));
+        assert!(!glob_match("src/db/*.rs", "src/db/query.rsx"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn match_tag() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let drain = LogBuilder::new(Counter(count.clone()))
+            .parse("info,[@tag=slog-async]=off")
+            .build();
+        let logger = slog::Logger::root(drain.fuse(), o!());
+        slog_info!(logger, #"slog-async", "silenced");
+        slog_info!(logger, #"audit", "passed");
+        slog_info!(logger, "passed");
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn match_path_boundary() {
+        let logger = LogBuilder::new(slog::Discard)
+            .parse("error,foo=debug")
+            .build();
+        assert!(logger.is_enabled(Level::Debug, "foo"));
+        assert!(logger.is_enabled(Level::Debug, "foo::bar"));
+        assert!(!logger.is_enabled(Level::Debug, "foobar"));
+        assert!(!logger.is_enabled(Level::Debug, "foo_bar::baz"));
+    }
+
+    #[test]
+    fn match_glob() {
+        let logger = LogBuilder::new(slog::Discard)
+            .parse("warn,*::db=debug,hyper::*=off")
+            .build();
+        assert!(logger.is_enabled(Level::Debug, "crate1::db"));
+        assert!(logger.is_enabled(Level::Debug, "crate1::db::pool"));
+        assert!(!logger.is_enabled(Level::Debug, "crate1::dbx"));
+        assert!(!logger.is_enabled(Level::Debug, "db"));
+        assert!(!logger.is_enabled(Level::Error, "hyper::client"));
+        assert!(logger.is_enabled(Level::Warning, "hyper"));
+    }
+
+    #[test]
+    fn match_glob_and_plain() {
+        // the deeper glob wins over the longer name
+        let logger = LogBuilder::new(slog::Discard)
+            .parse("tokio_postgres=info,*::db=trace")
+            .build();
+        assert!(logger.is_enabled(Level::Trace, "tokio_postgres::db"));
+        assert!(!logger.is_enabled(Level::Debug, "tokio_postgres::client"));
+
+        // no matter the spec order
+        for spec in &["-hyper,*::db=trace", "*::db=trace,-hyper"] {
+            let logger = LogBuilder::new(slog::Discard).parse(spec).build();
+            assert!(logger.is_enabled(Level::Trace, "hyper::db"), "{}", spec);
+            assert!(!logger.is_enabled(Level::Critical, "hyper::client"), "{}", spec);
+        }
+
+        // at the same depth the segments without globs win
+        for spec in &["hyper::*=off,*::db=debug,hyper::db=trace",
+                      "hyper::db=trace,*::db=debug,hyper::*=off"] {
+            let logger = LogBuilder::new(slog::Discard).parse(spec).build();
+            assert!(logger.is_enabled(Level::Trace, "hyper::db"), "{}", spec);
+            assert!(!logger.is_enabled(Level::Critical, "hyper::client"), "{}", spec);
+            assert!(!logger.is_enabled(Level::Trace, "app::db"), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn exclude() {
+        let logger = LogBuilder::new(slog::Discard)
+            .parse("debug,-noisy_crate,noisy_crate::important=info")
+            .build();
+        assert!(!logger.is_enabled(Level::Critical, "noisy_crate"));
+        assert!(!logger.is_enabled(Level::Error, "noisy_crate::inner"));
+        assert!(logger.is_enabled(Level::Info, "noisy_crate::important"));
+        assert!(logger.is_enabled(Level::Debug, "noisy_crate2"));
+
+        let dirs = parse_logging_spec("-noisy_crate");
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].name, Some("noisy_crate".to_string()));
+        assert_eq!(dirs[0].level, FilterLevel::Off);
+
+        let err = LogBuilder::new(slog::Discard)
+            .try_parse("-noisy_crate=info")
+            .err()
+            .unwrap();
+        assert_eq!(err.reason, "invalid exclude directive '-noisy_crate=info'");
+    }
+
+    #[test]
+    fn shift_verbosity() {
+        let (logger, handle) = LogBuilder::new(slog::Discard)
+            .parse("info,crate1=debug,-crate2")
+            .build_with_handle();
+        assert_eq!(handle.shift_verbosity(1), FilterLevel::Trace);
+        assert!(logger.is_enabled(Level::Debug, "crate3"));
+        assert!(logger.is_enabled(Level::Trace, "crate1"));
+        assert!(!logger.is_enabled(Level::Critical, "crate2"));
+
+        // the trace level is clamped, lowering is still relative to the spec
+        handle.shift_verbosity(1);
+        assert_eq!(handle.shift_verbosity(-3), FilterLevel::Info);
+        assert_eq!(handle.verbosity(), -1);
+        assert!(!logger.is_enabled(Level::Info, "crate3"));
+        assert!(logger.is_enabled(Level::Info, "crate1"));
+
+        assert_eq!(handle.shift_verbosity(-10), FilterLevel::Critical);
+        assert!(logger.is_enabled(Level::Critical, "crate3"));
+
+        handle.set_spec("info").unwrap();
+        assert_eq!(handle.verbosity(), 0);
+    }
+
+    #[test]
+    fn shift_verbosity_concurrent() {
+        let (_logger, handle) = LogBuilder::new(slog::Discard)
+            .parse("info")
+            .build_with_handle();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || for _ in 0..100 {
+                    handle.shift_verbosity(1);
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(handle.verbosity(), 400);
+    }
+
+    #[test]
+    fn introspection() {
+        let logger = LogBuilder::new(slog::Discard)
+            .parse("info,crate1[@tag=audit]=trace,crate1::db=debug/^select")
+            .build();
+        assert_eq!(logger.to_string(), "info,crate1::db=debug/^select,crate1[@tag=audit]=trace");
+        assert_eq!(
+            logger.directives()[1],
+            Directive {
+                module: Some("crate1::db".to_string()),
+                level: FilterLevel::Debug,
+                filter: Some("^select".to_string()),
+                predicates: Vec::new(),
+            }
+        );
+        assert_eq!(logger.directives()[2].predicates, vec!["@tag=audit".to_string()]);
+
+        // the predicate directive might let the trace records through
+        assert!(logger.is_enabled(Level::Trace, "crate1::db"));
+        assert!(!logger.is_enabled(Level::Debug, "crate2"));
+        assert!(Drain::is_enabled(&logger, Level::Trace));
+
+        let logger = LogBuilder::new(slog::Discard).parse("warn").build();
+        assert!(!Drain::is_enabled(&logger, Level::Info));
+        assert!(!Drain::is_enabled(&logger.fuse(), Level::Info));
+    }
+
+    #[test]
+    fn cached_decisions() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (drain, handle) = LogBuilder::new(Counter(count.clone()))
+            .parse("info,co_slog=debug/^hit")
+            .build_with_handle();
+        let logger = slog::Logger::root(drain.fuse(), o!());
+        for i in 0..3 {
+            slog_debug!(logger, "hit {}", i);
+            slog_debug!(logger, "miss {}", i);
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+
+        // the cached decisions are dropped with the old rules
+        handle.set_spec("info,co_slog::env_drain=warn").unwrap();
+        slog_debug!(logger, "hit");
+        slog_warn!(logger, "miss");
+        assert_eq!(count.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn module_cache() {
+        let cache = ModuleCache::new();
+        assert!(cache.get((1, 2)).is_none());
+        let rules = Rules::new(parse_logging_spec("crate1=info,crate1::mod1=warn"),
+                               FilterLevel::Error);
+        cache.insert((1, 2), rules.match_module("crate1::mod1::db"));
+        let m = cache.get((1, 2)).unwrap();
+        assert_eq!(rules.directives[m.plain.unwrap()].level, FilterLevel::Warning);
+        assert!(!m.has_preds);
+
+        // the same address with another length is another module
+        assert!(cache.get((1, 3)).is_none());
+        cache.insert((1, 3), rules.match_module("crate2"));
+        assert!(cache.get((1, 3)).unwrap().plain.is_none());
+        assert!(cache.get((1, 2)).unwrap().plain.is_some());
+
+        // a full table leaves the modules out
+        for i in 0..2 * super::MODULE_CACHE_SIZE {
+            cache.insert((i + 10, 1), rules.match_module("crate1"));
+        }
+        assert!(cache.get((1, 2)).is_some());
+    }
+
+    #[test]
+    fn try_parse_error() {
+        let err = LogBuilder::new(slog::Discard)
+            .try_parse("info,crate1::mod1=wrong,crate2=debug")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            SpecError {
+                directive: "crate1::mod1=wrong".to_string(),
+                span: 5..23,
+                reason: "invalid log level 'wrong'".to_string(),
+            }
+        );
+
+        let err = LogBuilder::new(slog::Discard)
+            .try_parse("crate1=debug/(,crate2")
+            .err()
+            .unwrap();
+        assert_eq!(err.span, 0..14);
+        assert!(err.reason.starts_with("invalid regex filter"));
+
+        let err = LogBuilder::new(slog::Discard)
+            .try_parse("crate1=warn=info")
+            .err()
+            .unwrap();
+        assert_eq!(err.reason, "too many '='s");
+
+        assert!(LogBuilder::new(slog::Discard).try_parse("info,crate1=debug/a").is_ok());
+    }
+
+    #[test]
+    fn handle_set_invalid_spec() {
+        let (logger, handle) = LogBuilder::new(slog::Discard)
+            .parse("info")
+            .build_with_handle();
+        assert!(handle.set_spec("crate1=trace,crate2[peer]=debug").is_err());
+        assert_eq!(handle.current_spec(), "info");
+        assert!(!logger.is_enabled(Level::Trace, "crate1"));
+    }
+
+    #[test]
+    fn env_chain() {
+        use std::env;
+        env::set_var("CO_SLOG_TEST_APP_LOG", "crate1=debug");
+        env::set_var("CO_SLOG_TEST_FALLBACK_LOG", "crate2=trace");
+        env::remove_var("CO_SLOG_TEST_UNSET_LOG");
+
+        let logger = LogBuilder::from_env(slog::Discard, "CO_SLOG_TEST_UNSET_LOG")
+            .or_env("CO_SLOG_TEST_APP_LOG")
+            .or_env("CO_SLOG_TEST_FALLBACK_LOG")
+            .or_spec("info")
+            .build();
+        assert!(logger.is_enabled(Level::Debug, "crate1"));
+        assert!(!logger.is_enabled(Level::Trace, "crate2"));
+
+        let logger = LogBuilder::from_env(slog::Discard, "CO_SLOG_TEST_UNSET_LOG")
+            .or_spec("info")
+            .build();
+        assert!(logger.is_enabled(Level::Info, "crate1"));
+        assert!(!logger.is_enabled(Level::Debug, "crate1"));
+    }
+
+    #[test]
+    fn default_level_verbosity() {
+        let logger = LogBuilder::new(slog::Discard)
+            .default_level(FilterLevel::Info)
+            .build();
+        assert!(logger.is_enabled(Level::Info, "crate1"));
+        assert!(!logger.is_enabled(Level::Debug, "crate1"));
+
+        let logger = LogBuilder::new(slog::Discard)
+            .default_level(FilterLevel::Warning)
+            .verbosity(2)
+            .build();
+        assert_eq!(logger.filter(), FilterLevel::Debug);
+
+        let logger = LogBuilder::new(slog::Discard).verbosity(-1).build();
+        assert_eq!(logger.filter(), FilterLevel::Critical);
+
+        let logger = LogBuilder::new(slog::Discard).verbosity(100).build();
+        assert_eq!(logger.filter(), FilterLevel::Trace);
+
+        let logger = LogBuilder::new(slog::Discard).verbosity(-100).build();
+        assert_eq!(logger.filter(), FilterLevel::Off);
+
+        // the directives take precedence
+        let logger = LogBuilder::new(slog::Discard)
+            .verbosity(3)
+            .parse("crate1=warn")
+            .build();
+        assert!(!logger.is_enabled(Level::Info, "crate1"));
     }
 }
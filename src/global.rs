@@ -0,0 +1,245 @@
+//! The global logger, used when the scope stack is empty
+//!
+//! The global logger is a stack of overrides on top of a base logger (the
+//! default `EnvDrain` logger on stderr). Every override is tagged with a
+//! generation number, so dropping the guards in any order, from any thread,
+//! only removes its own entry and never restores a wrong logger.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use crossbeam::sync::ArcCell;
+use slog::Logger;
+use env_drain::{self, FilterHandle};
+
+struct GlobalState {
+    base: Logger,
+    overrides: Vec<(u64, Logger)>,
+    next_generation: u64,
+}
+
+impl GlobalState {
+    fn current_mut(&mut self) -> &mut Logger {
+        match self.overrides.last_mut() {
+            Some(&mut (_, ref mut l)) => l,
+            None => &mut self.base,
+        }
+    }
+
+    /// publish the current top logger to the lock free read path
+    ///
+    /// Return the previously published logger, drop it after releasing the
+    /// state lock: the last clone of a logger drops its drain, which may log
+    /// or change the global logger itself.
+    fn publish(&mut self) -> Arc<Logger> {
+        let logger = self.current_mut().clone();
+        GLOBAL_LOGGER.set(Arc::new(logger))
+    }
+}
+
+/// Use a default `EnvLogger` as global logging drain
+lazy_static! {
+    static ref DEFAULT_LOGGER : (Logger, FilterHandle) = env_drain::stderr_logger();
+
+    static ref GLOBAL_LOGGER : ArcCell<Logger> = {
+        ArcCell::new(Arc::new(DEFAULT_LOGGER.0.clone()))
+    };
+
+    static ref GLOBAL_STATE : Mutex<GlobalState> = {
+        Mutex::new(GlobalState {
+            base: (*GLOBAL_LOGGER.get()).clone(),
+            overrides: Vec::new(),
+            next_generation: 0,
+        })
+    };
+}
+
+fn state() -> MutexGuard<'static, GlobalState> {
+    // the state is always consistent, a poisoned lock is still usable
+    GLOBAL_STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// serialize the tests that change the global logger
+#[cfg(test)]
+pub(crate) fn test_lock() -> MutexGuard<'static, ()> {
+    TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// return the current global logger
+pub(crate) fn current() -> Arc<Logger> {
+    GLOBAL_LOGGER.get()
+}
+
+/// return the filter handle of the default `EnvDrain` logger
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) fn default_filter() -> FilterHandle {
+    DEFAULT_LOGGER.1.clone()
+}
+
+/// Global logger guard
+///
+/// On drop the logger installed by `set_global_logger` is removed and the
+/// previous global logger comes back. Call `cancel_reset` to keep the logger
+/// installed for the rest of the program.
+#[must_use]
+pub struct GlobalLoggerGuard {
+    generation: u64,
+    canceled: bool,
+}
+
+impl GlobalLoggerGuard {
+    /// Keep the logger installed after the guard is gone
+    pub fn cancel_reset(mut self) {
+        self.canceled = true;
+    }
+}
+
+impl Drop for GlobalLoggerGuard {
+    fn drop(&mut self) {
+        if self.canceled {
+            return;
+        }
+        let generation = self.generation;
+        let _removed = {
+            let mut state = state();
+            match state.overrides.iter().position(|e| e.0 == generation) {
+                Some(pos) => (state.overrides.remove(pos), state.publish()),
+                None => return,
+            }
+        };
+    }
+}
+
+/// Set global `Logger`
+///
+/// The logger stays installed until the returned guard is dropped, no matter
+/// which thread/coroutine drops it. Overrides stack, the most recent alive one
+/// is used.
+pub fn set_global_logger(l: Logger) -> GlobalLoggerGuard {
+    let mut state = state();
+    let generation = state.next_generation;
+    state.next_generation += 1;
+    state.overrides.push((generation, l));
+    let old = state.publish();
+    drop(state);
+    drop(old);
+    GlobalLoggerGuard {
+        generation: generation,
+        canceled: false,
+    }
+}
+
+/// Set global `Logger` for the rest of the program
+///
+/// The same as `set_global_logger(l).cancel_reset()`.
+pub fn set_global_logger_permanent(l: Logger) {
+    set_global_logger(l).cancel_reset()
+}
+
+/// Access the current global `Logger`
+pub fn global_logger() -> Logger {
+    (*current()).clone()
+}
+
+/// Replace the current global `Logger`, return the previous one
+///
+/// Unlike `set_global_logger` this doesn't push a new override, the logger
+/// takes the place of the current one and is owned by its guard (if any).
+pub fn swap_global_logger(l: Logger) -> Logger {
+    let mut state = state();
+    let old = ::std::mem::replace(state.current_mut(), l);
+    let published = state.publish();
+    drop(state);
+    drop(published);
+    old
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use slog::{self, Drain, Logger, OwnedKVList, Record};
+
+    use super::{global_logger, set_global_logger, swap_global_logger, test_lock};
+    use test_drains::Counter;
+
+    fn counted() -> (Logger, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        (Logger::root(Counter(count.clone()), o!()), count)
+    }
+
+    fn counts(counts: &[&Arc<AtomicUsize>]) -> Vec<usize> {
+        counts.iter().map(|c| c.load(Ordering::Relaxed)).collect()
+    }
+
+    /// a drain setting a global logger when dropped
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drain for SetOnDrop {
+        type Ok = ();
+        type Err = slog::Never;
+        fn log(&self, _: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            Ok(())
+        }
+    }
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            drop(set_global_logger(Logger::root(slog::Discard, o!())));
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn guards_dropped_out_of_order_across_threads() {
+        let _lock = test_lock();
+        let (base, base_count) = counted();
+        let (a, a_count) = counted();
+        let (b, b_count) = counted();
+        let base_guard = set_global_logger(base);
+        let guard_a = set_global_logger(a);
+        let guard_b = set_global_logger(b);
+
+        // dropping the older override keeps the newer one installed
+        thread::spawn(move || drop(guard_a)).join().unwrap();
+        slog_info!(global_logger(), "b");
+        assert_eq!(counts(&[&base_count, &a_count, &b_count]), vec![0, 0, 1]);
+
+        thread::spawn(move || drop(guard_b)).join().unwrap();
+        slog_info!(global_logger(), "base");
+        assert_eq!(counts(&[&base_count, &a_count, &b_count]), vec![1, 0, 1]);
+        drop(base_guard);
+    }
+
+    #[test]
+    fn swap_returns_previous() {
+        let _lock = test_lock();
+        let (a, a_count) = counted();
+        let (b, b_count) = counted();
+        let guard = set_global_logger(a);
+
+        let old = swap_global_logger(b);
+        slog_info!(old, "a");
+        slog_info!(global_logger(), "b");
+        assert_eq!(counts(&[&a_count, &b_count]), vec![1, 1]);
+
+        // the swapped in logger is owned by the guard
+        drop(guard);
+        slog_debug!(global_logger(), "default");
+        assert_eq!(counts(&[&a_count, &b_count]), vec![1, 1]);
+    }
+
+    #[test]
+    fn drop_outside_the_lock() {
+        let _lock = test_lock();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = set_global_logger(Logger::root(SetOnDrop(dropped.clone()), o!()));
+        // the last clones go with the guard, the drain can take the lock again
+        drop(guard);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,70 @@
+//! Coroutine identity keys
+//!
+//! When enabled, records logged through `with_logger` (and thus the logging
+//! macros) carry the id and name of the logging coroutine and the id of the
+//! OS worker thread it runs on:
+//!
+//! * `co_id` - unique id of the coroutine (or the thread if not in a coroutine)
+//! * `co_name` - name of the coroutine/thread, if set
+//! * `thread` - id of the OS thread that executed the logging call
+//!
+//! The values are captured at the logging call site, so they stay correct
+//! when the record is processed later by an `AsyncDrain` worker.
+
+use std::thread::{self, ThreadId};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use may::coroutine;
+use slog::{self, Logger, Key, Record, Serializer, Value};
+
+static ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+static NEXT_CO_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+struct Identity {
+    id: usize,
+    name: Option<String>,
+}
+
+coroutine_local! {
+    static IDENTITY: Identity = {
+        let name = if coroutine::is_coroutine() {
+            coroutine::current().name().map(|s| s.to_owned())
+        } else {
+            thread::current().name().map(|s| s.to_owned())
+        };
+        Identity {
+            id: NEXT_CO_ID.fetch_add(1, Ordering::Relaxed),
+            name: name,
+        }
+    }
+}
+
+/// the OS thread id as a logging value
+struct ThreadIdValue(ThreadId);
+
+impl Value for ThreadIdValue {
+    fn serialize(&self, _record: &Record, key: Key, serializer: &mut Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{:?}", self.0))
+    }
+}
+
+/// Enable or disable the coroutine identity keys on every scope record
+pub fn enable_coroutine_keys(enable: bool) {
+    ENABLED.store(enable, Ordering::Relaxed);
+}
+
+/// call `f` with `logger`, extended with the identity keys if enabled
+pub(crate) fn with_keys<F, R>(logger: &Logger, f: F) -> R
+where
+    F: FnOnce(&Logger) -> R,
+{
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f(logger);
+    }
+
+    let logger = IDENTITY.with(|i| {
+        logger.new(o!("co_id" => i.id,
+                      "co_name" => i.name.clone(),
+                      "thread" => ThreadIdValue(thread::current().id())))
+    });
+    f(&logger)
+}
@@ -34,7 +34,7 @@
 //! }
 #![warn(missing_docs)]
 
-#[macro_use(coroutine_local)]
+#[macro_use(coroutine_local, go)]
 extern crate may;
 #[macro_use]
 extern crate slog;
@@ -44,112 +44,192 @@ extern crate slog_term;
 extern crate crossbeam;
 #[macro_use]
 extern crate lazy_static;
+extern crate backtrace;
+extern crate log;
+extern crate chrono;
+#[cfg(unix)]
+extern crate libc;
 
 mod env_drain;
 mod mutex_drain;
 mod async_drain;
+mod global;
+mod identity;
+mod span;
+mod panic_hook;
+mod log_bridge;
+mod snapshot;
+#[cfg(unix)]
+mod signal;
+pub mod coroutine;
+pub mod thread;
+#[cfg(test)]
+mod test_drains;
 
 use slog::Logger;
-use std::sync::Arc;
 use std::cell::RefCell;
-use crossbeam::sync::ArcCell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
-pub use slog::Drain;
-pub use env_drain::EnvDrain;
-pub use async_drain::AsyncDrain;
+pub use slog::{Drain, Level};
+pub use env_drain::{EnvDrain, LogBuilder, FilterHandle, SpecError, Directive};
+pub use async_drain::{AsyncDrain, AsyncError, OverflowPolicy, record_time, record_instant,
+                      timestamp_local, timestamp_utc};
 pub use mutex_drain::MutexDrain;
+pub use coroutine::spawn;
+pub use identity::enable_coroutine_keys;
+pub use span::SpanGuard;
+pub use panic_hook::{install_panic_hook, PanicHook};
+pub use snapshot::{ScopeSnapshot, SnapshotGuard};
+pub use log_bridge::{init_log_bridge, init_log_bridge_with_spec, LogBridge};
+#[cfg(unix)]
+pub use signal::{listen_verbosity_signals, VerbositySignals};
+pub use global::{set_global_logger, set_global_logger_permanent, global_logger,
+                 swap_global_logger, GlobalLoggerGuard};
 
 /// Log a critical level message using current scope logger
 #[macro_export]
 macro_rules! crit( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_crit![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Critical, |logger| slog_crit![logger, $($args)+])
 };);
 /// Log a error level message using current scope logger
 #[macro_export]
 macro_rules! error( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_error![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Error, |logger| slog_error![logger, $($args)+])
 };);
 /// Log a warning level message using current scope logger
 #[macro_export]
 macro_rules! warn( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_warn![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Warning, |logger| slog_warn![logger, $($args)+])
 };);
 /// Log a info level message using current scope logger
 #[macro_export]
 macro_rules! info( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_info![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Info, |logger| slog_info![logger, $($args)+])
 };);
 /// Log a debug level message using current scope logger
 #[macro_export]
 macro_rules! debug( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_debug![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Debug, |logger| slog_debug![logger, $($args)+])
 };);
 /// Log a trace level message using current scope logger
 #[macro_export]
 macro_rules! trace( ($($args:tt)+) => {
-    $crate::with_logger(|logger| slog_trace![logger, $($args)+])
+    $crate::with_enabled_logger($crate::Level::Trace, |logger| slog_trace![logger, $($args)+])
 };);
 
-/// Use a default `EnvLogger` as global logging drain
-lazy_static! {
-    static ref ENV_LOGGER : slog::Logger = {
-        env_drain::stderr_logger()
+/// Enter a timed span in the current logging scope
+///
+/// `let _span = span!("db_query"; "table" => t);` pushes a child logger with
+/// the span name, depth and the key-values, and logs the enter record. When
+/// `_span` is dropped the exit record with the elapsed time and the outcome
+/// is logged.
+#[macro_export]
+macro_rules! span(
+    ($name:expr) => {
+        $crate::SpanGuard::new($name, slog_o!())
     };
-
-    static ref GLOBAL_LOGGER : ArcCell<slog::Logger> = {
-        ArcCell::new(Arc::new(ENV_LOGGER.clone()))
+    ($name:expr; $($args:tt)+) => {
+        $crate::SpanGuard::new($name, slog_o!($($args)+))
     };
-}
-
+);
 
-/// On drop it will reset global logger to `ENV_LOGGER`.
-struct GlobalLoggerGuard;
-impl Drop for GlobalLoggerGuard {
-    fn drop(&mut self) {
-        GLOBAL_LOGGER.set(Arc::new(ENV_LOGGER.clone()));
-    }
-}
-
-/// the global logger guard, valid for the whole life of the thread/coroutine
-coroutine_local! {
-    static GLOBAL_GUARD: RefCell<Option<GlobalLoggerGuard>> = {
-        RefCell::new(None)
-    }
+/// Run the blocks in parallel coroutines and wait for all of them
+///
+/// It's the same as `may::join!` except that every coroutine inherits the
+/// current scope logger.
+#[macro_export]
+macro_rules! join {
+    ($($body:expr),+) => ({
+        $crate::coroutine::scope(|s| {
+            $(
+                s.spawn(|| { $body; });
+            )+
+        })
+    })
 }
 
-/// Set global `Logger`
-/// if the setter thread/coroutine exit,
-/// the global logger would reset to default EVN_LOGGER
-pub fn set_global_logger(l: slog::Logger) {
-    GLOBAL_LOGGER.set(Arc::new(l));
-    GLOBAL_GUARD.with(|g| *g.borrow_mut() = Some(GlobalLoggerGuard));
+/// an entry of the logger stack, tagged with the id of the guard that owns it
+struct Scope {
+    id: usize,
+    logger: slog::Logger,
 }
 
 /// the logger stack infrustructure
 coroutine_local! {
-    static TL_SCOPES: RefCell<Vec<slog::Logger>> = {
+    static TL_SCOPES: RefCell<Vec<Scope>> = {
         RefCell::new(Vec::with_capacity(8))
     }
 }
 
+/// unique id source for `ScopeGuard`
+static NEXT_SCOPE_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
 /// scope logger guard, when dropped would pop it's own logger
-pub struct ScopeGuard;
+///
+/// Each guard remembers the identity and the depth of the entry it pushed.
+/// If guards are dropped out of order only the guard's own entry is removed
+/// and a warning is logged, so the rest of the stack stays intact.
+///
+/// The guard is `!Send`, it must be dropped in the coroutine that created it.
+pub struct ScopeGuard {
+    id: usize,
+    depth: usize,
+    _not_send: PhantomData<*const ()>,
+}
 
 impl ScopeGuard {
     /// push
     fn new(logger: slog::Logger) -> Self {
-        TL_SCOPES.with(|s| s.borrow_mut().push(logger));
-        ScopeGuard
+        let id = NEXT_SCOPE_ID.fetch_add(1, Ordering::Relaxed);
+        let depth = TL_SCOPES.with(|s| {
+            let mut s = s.borrow_mut();
+            s.push(Scope {
+                id: id,
+                logger: logger,
+            });
+            s.len() - 1
+        });
+        ScopeGuard {
+            id: id,
+            depth: depth,
+            _not_send: PhantomData,
+        }
     }
 }
 
 impl Drop for ScopeGuard {
     fn drop(&mut self) {
-        TL_SCOPES.with(|s| {
-            s.borrow_mut().pop().expect(
-                "TL_SCOPES should contain a logger",
-            );
-        })
+        // the stack must not be borrowed while logging the misuse report
+        let (scope, found, in_order) = TL_SCOPES.with(|s| {
+            let mut s = s.borrow_mut();
+            let top = s.len();
+            if s.last().map(|e| e.id) == Some(self.id) {
+                return (s.pop(), top - 1, true);
+            }
+            match s.iter().rposition(|e| e.id == self.id) {
+                Some(pos) => (Some(s.remove(pos)), pos, false),
+                None => (None, top, false),
+            }
+        });
+
+        match scope {
+            Some(scope) => {
+                if !in_order {
+                    slog_warn!(scope.logger, "ScopeGuard dropped out of order";
+                               "depth" => self.depth, "found_at" => found)
+                }
+                if ::std::thread::panicking() && panic_hook::report_aborted_scope() {
+                    slog_error!(scope.logger, "scope aborted"; "depth" => self.depth)
+                }
+            }
+            None => {
+                with_logger(|l| {
+                    slog_warn!(l, "ScopeGuard dropped without its scope";
+                               "depth" => self.depth, "stack_depth" => found)
+                })
+            }
+        }
     }
 }
 
@@ -159,6 +239,38 @@ pub fn set_logger(logger: slog::Logger) -> ScopeGuard {
     ScopeGuard::new(logger)
 }
 
+/// Execute the closure with `logger` as the current scope logger
+///
+/// The logger is popped when the closure returns (or unwinds), the closure
+/// return value is passed through.
+pub fn scope<F, R>(logger: slog::Logger, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = ScopeGuard::new(logger);
+    f()
+}
+
+/// push a child of the current scope logger with the extra key-values
+///
+/// ```ignore
+/// let _guard = co_slog::with_values(o!("request_id" => id));
+/// ```
+/// is a shortcut of `co_slog::set_logger(co_slog::logger().new(o!(...)))`.
+pub fn with_values<T>(values: slog::OwnedKV<T>) -> ScopeGuard
+where
+    T: slog::SendSyncRefUnwindSafeKV + 'static,
+{
+    // not `with_logger`, the coroutine keys must not be baked into the scope
+    let logger = logger().new(values);
+    ScopeGuard::new(logger)
+}
+
+/// return the `Logger` on top of the scope stack, if any
+fn current_scope() -> Option<Logger> {
+    TL_SCOPES.with(|s| s.borrow().last().map(|e| e.logger.clone()))
+}
+
 /// Access the `Logger` for the current logging scope
 ///
 /// This function needs to clone an underlying scoped
@@ -168,8 +280,48 @@ pub fn logger() -> Logger {
     TL_SCOPES.with(|s| {
         let s = s.borrow();
         match s.last() {
-            Some(logger) => logger.clone(),
-            None => (*GLOBAL_LOGGER.get()).clone(),
+            Some(scope) => scope.logger.clone(),
+            None => global_logger(),
+        }
+    })
+}
+
+/// Check if the current scope logger lets the level through
+///
+/// It asks the drains of the logger (see `Drain::is_enabled`), the logging
+/// macros of this crate do the same check to skip building the filtered out
+/// records.
+pub fn is_enabled(level: Level) -> bool {
+    TL_SCOPES.with(|s| {
+        let s = s.borrow();
+        match s.last() {
+            Some(scope) => scope.logger.is_enabled(level),
+            None => global::current().is_enabled(level),
+        }
+    })
+}
+
+/// `with_logger` for the logging macros, `f` only runs if the current scope
+/// logger lets the level through
+#[doc(hidden)]
+pub fn with_enabled_logger<F>(level: Level, f: F)
+where
+    F: FnOnce(&Logger),
+{
+    TL_SCOPES.with(|s| {
+        let s = s.borrow();
+        match s.last() {
+            Some(scope) => {
+                if scope.logger.is_enabled(level) {
+                    identity::with_keys(&scope.logger, f)
+                }
+            }
+            None => {
+                let logger = global::current();
+                if logger.is_enabled(level) {
+                    identity::with_keys(&logger, f)
+                }
+            }
         }
     })
 }
@@ -178,6 +330,9 @@ pub fn logger() -> Logger {
 ///
 /// This function doesn't have to clone the Logger
 /// so it might be a bit faster.
+///
+/// The logger carries the coroutine identity keys if enabled by
+/// `enable_coroutine_keys`.
 pub fn with_logger<F, R>(f: F) -> R
 where
     F: FnOnce(&Logger) -> R,
@@ -185,8 +340,109 @@ where
     TL_SCOPES.with(|s| {
         let s = s.borrow();
         match s.last() {
-            Some(logger) => f(logger),
-            None => f(&(*GLOBAL_LOGGER.get())),
+            Some(scope) => identity::with_keys(&scope.logger, f),
+            None => identity::with_keys(&global::current(), f),
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use slog::Level;
+
+    use super::{set_logger, spawn, with_logger, ScopeSnapshot, TL_SCOPES};
+    use {coroutine, thread};
+    use test_drains::recorder;
+
+    fn depth() -> usize {
+        TL_SCOPES.with(|s| s.borrow().len())
+    }
+
+    #[test]
+    fn scope_guard_out_of_order() {
+        let (a, a_logged) = recorder();
+        let (b, b_logged) = recorder();
+        let guard_a = set_logger(a);
+        let guard_b = set_logger(b);
+
+        // only A is removed, B stays on top
+        drop(guard_a);
+        let warnings = a_logged.entries();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].level, Level::Warning);
+        assert_eq!(warnings[0].msg, "ScopeGuard dropped out of order");
+        with_logger(|l| slog_info!(l, "on top"));
+        assert_eq!(b_logged.messages(), vec!["on top"]);
+
+        drop(guard_b);
+        assert_eq!(a_logged.entries().len(), 1);
+        assert_eq!(b_logged.entries().len(), 1);
+    }
+
+    #[test]
+    fn coroutines_inherit_scope() {
+        let (logger, logged) = recorder();
+        let _guard = set_logger(logger);
+
+        spawn(|| info!("spawned")).join().unwrap();
+        coroutine::scope(|s| {
+            s.spawn(|| info!("scoped"));
+        });
+        join!(info!("joined a"), info!("joined b"));
+
+        let mut messages = logged.messages();
+        // the joined coroutines run in any order
+        messages[2..].sort();
+        assert_eq!(messages, vec!["spawned", "scoped", "joined a", "joined b"]);
+    }
+
+    #[test]
+    fn threads_inherit_scope() {
+        let (logger, logged) = recorder();
+        let _guard = set_logger(logger);
+
+        thread::spawn(|| info!("spawned")).join().unwrap();
+        thread::Builder::new()
+            .name("child".to_owned())
+            .spawn(|| info!("built"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(logged.messages(), vec!["spawned", "built"]);
+    }
+
+    #[test]
+    fn snapshot_enter() {
+        let (a, a_logged) = recorder();
+        let (b, b_logged) = recorder();
+        let (base, base_logged) = recorder();
+        let guard_a = set_logger(a);
+        let guard_b = set_logger(b);
+        let top = ScopeSnapshot::capture();
+        let stack = ScopeSnapshot::capture_stack();
+        drop(guard_b);
+        drop(guard_a);
+
+        ::std::thread::spawn(move || {
+            let _guard = set_logger(base);
+            {
+                let _entered = top.enter();
+                assert_eq!(depth(), 2);
+                info!("top");
+            }
+            info!("base");
+            {
+                let _entered = stack.enter();
+                assert_eq!(depth(), 3);
+                info!("stack");
+            }
+            assert_eq!(depth(), 1);
+            info!("base again");
+        }).join().unwrap();
+
+        // popped in order, no warnings logged
+        assert!(a_logged.entries().is_empty());
+        assert_eq!(b_logged.messages(), vec!["top", "stack"]);
+        assert_eq!(base_logged.messages(), vec!["base", "base again"]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Bridge from the `log` crate into co_slog scopes
+//!
+//! After `init_log_bridge` every `log` crate record is forwarded to the current
+//! scope logger through `with_logger`, just like it was logged by the `info!`
+//! (and friends) macros of this crate. The `log` target is used as the record
+//! module, so `EnvDrain` directives apply to it, and the record is tagged with
+//! `"log"`. The targets are kept for the rest of the program, after
+//! 1024 (`MAX_TARGETS`) distinct ones the new targets use the `"log"` module.
+//!
+//! The `log` max level is left at `trace`, so the rules changed at runtime
+//! (`FilterHandle`, verbosity signals) apply to the `log` records too.
+//! `LogBridge::enabled` asks the current scope logger.
+
+use std::collections::HashSet;
+use std::sync::RwLock;
+use log;
+use slog::{self, Level, FilterLevel};
+use env_drain::LogBuilder;
+use {is_enabled, with_logger};
+
+/// the most `log` targets interned, targets built at runtime must not leak
+/// without a bound
+const MAX_TARGETS: usize = 1024;
+
+lazy_static! {
+    /// interned `log` targets, `slog::Record` needs `&'static str` module
+    static ref TARGETS: RwLock<HashSet<&'static str>> = {
+        RwLock::new(HashSet::new())
+    };
+}
+
+fn intern(target: &str) -> &'static str {
+    if let Some(s) = TARGETS.read().unwrap_or_else(|e| e.into_inner()).get(target) {
+        return s;
+    }
+    let mut targets = TARGETS.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(s) = targets.get(target) {
+        return s;
+    }
+    if targets.len() >= MAX_TARGETS {
+        return "log";
+    }
+    let s: &'static str = Box::leak(target.to_owned().into_boxed_str());
+    targets.insert(s);
+    s
+}
+
+fn to_slog_level(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::Error,
+        log::Level::Warn => Level::Warning,
+        log::Level::Info => Level::Info,
+        log::Level::Debug => Level::Debug,
+        log::Level::Trace => Level::Trace,
+    }
+}
+
+fn to_log_filter(level: FilterLevel) -> log::LevelFilter {
+    match level {
+        FilterLevel::Off => log::LevelFilter::Off,
+        FilterLevel::Critical | FilterLevel::Error => log::LevelFilter::Error,
+        FilterLevel::Warning => log::LevelFilter::Warn,
+        FilterLevel::Info => log::LevelFilter::Info,
+        FilterLevel::Debug => log::LevelFilter::Debug,
+        FilterLevel::Trace => log::LevelFilter::Trace,
+    }
+}
+
+/// `log::Log` implementation that forwards records to the current scope logger
+pub struct LogBridge;
+
+impl log::Log for LogBridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        is_enabled(to_slog_level(metadata.level()))
+    }
+
+    fn log(&self, r: &log::Record) {
+        if !self.enabled(r.metadata()) {
+            return;
+        }
+        let location = slog::RecordLocation {
+            file: r.file_static().unwrap_or("<unknown>"),
+            line: r.line().unwrap_or(0),
+            column: 0,
+            function: "",
+            module: intern(r.target()),
+        };
+        let s = slog::RecordStatic {
+            location: &location,
+            level: to_slog_level(r.level()),
+            tag: "log",
+        };
+        with_logger(|l| l.log(&slog::Record::new(&s, r.args(), b!())))
+    }
+
+    fn flush(&self) {}
+}
+
+/// Register `LogBridge` as the `log` backend
+///
+/// The records are filtered by the scope loggers, the `log` max level is
+/// `trace`.
+pub fn init_log_bridge() -> Result<(), log::SetLoggerError> {
+    init_log_bridge_with_level(FilterLevel::Trace)
+}
+
+/// Register `LogBridge` as the `log` backend
+///
+/// The `log` max level is fixed to the max level of the passed in spec, see
+/// `EnvDrain`. The `log` records above it are dropped up front, even if the
+/// rules are raised later on. Use `init_log_bridge` to follow the rules.
+pub fn init_log_bridge_with_spec(spec: &str) -> Result<(), log::SetLoggerError> {
+    init_log_bridge_with_level(LogBuilder::new(slog::Discard).parse(spec).build().filter())
+}
+
+fn init_log_bridge_with_level(level: FilterLevel) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(LogBridge))?;
+    log::set_max_level(to_log_filter(level));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{intern, MAX_TARGETS};
+
+    #[test]
+    fn intern_is_bounded() {
+        let first = intern("log_bridge::tests");
+        assert_eq!(first, "log_bridge::tests");
+        assert!(::std::ptr::eq(first, intern(&String::from("log_bridge::tests"))));
+
+        for i in 0..MAX_TARGETS {
+            intern(&format!("log_bridge::tests::{}", i));
+        }
+        assert_eq!(intern("log_bridge::tests::full"), "log");
+        // the targets already kept still resolve to themselves
+        assert_eq!(intern("log_bridge::tests"), "log_bridge::tests");
+    }
+}
@@ -1,8 +1,9 @@
-use std::fmt;
+use std::{fmt, io};
 use std::error::Error;
 use std::sync::PoisonError;
 use may::sync::{Mutex, MutexGuard};
-use slog::{Drain, Record, OwnedKVList};
+use slog::{Drain, FlushError, Level, Record, OwnedKVList};
+use panic_hook::DrainSection;
 
 /// Error returned by `Mutex<D : Drain>`
 #[derive(Clone)]
@@ -65,14 +66,27 @@ where
 }
 
 /// coroutine mutex based logger wrapper
+///
+/// The levels enabled by the wrapped drain are asked once when it's wrapped,
+/// so `is_enabled` doesn't take the lock. Later changes of the wrapped
+/// drain's levels are not seen by `is_enabled`, `log` still gets them.
 pub struct MutexDrain<D: Drain> {
     drain: Mutex<D>,
+    /// bit `n` is set if the level `n` is enabled
+    levels: usize,
 }
 
 impl<D: Drain> MutexDrain<D> {
     /// wrap a normal Drain to MutexDrain
     pub fn new(d: D) -> Self {
-        MutexDrain { drain: Mutex::new(d) }
+        let levels = (1..7)
+            .filter_map(Level::from_usize)
+            .filter(|l| d.is_enabled(*l))
+            .fold(0, |levels, l| levels | (1 << l.as_usize()));
+        MutexDrain {
+            drain: Mutex::new(d),
+            levels: levels,
+        }
     }
 }
 
@@ -80,8 +94,25 @@ impl<D: Drain> Drain for MutexDrain<D> {
     type Ok = D::Ok;
     type Err = MutexDrainError<D>;
     fn log(&self, record: &Record, logger_values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        // a panic under the lock must not log through this drain again
+        let _section = DrainSection::enter();
         self.drain.lock()?.log(record, logger_values).map_err(
             MutexDrainError::Drain,
         )
     }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.levels & (1 << level.as_usize()) != 0
+    }
+
+    fn flush(&self) -> Result<(), FlushError> {
+        let _section = DrainSection::enter();
+        match self.drain.lock() {
+            Ok(d) => d.flush(),
+            Err(_) => Err(FlushError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "Mutex acquire failed",
+            ))),
+        }
+    }
 }
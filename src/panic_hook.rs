@@ -0,0 +1,253 @@
+//! Panic hook that logs through the panicking coroutine's scope
+//!
+//! The hook logs the panic as a `crit` record through the current scope
+//! logger (or the global one) with the payload, the location and optionally a
+//! backtrace. Then it waits for the live `AsyncDrain` workers to write out the
+//! pending records before the previously installed hook runs.
+//!
+//! A panic raised while a drain is being used (e.g. under the `MutexDrain`
+//! lock) skips the logging, it would deadlock or panic again on that drain.
+
+use std::panic;
+use std::cell::Cell;
+use std::sync::{Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use std::time::Duration;
+use backtrace::Backtrace;
+use async_drain;
+use with_logger;
+
+static INSTALL: Once = ONCE_INIT;
+/// the hook does its work, it's only turned off again by the tests
+static ACTIVE: AtomicBool = ATOMIC_BOOL_INIT;
+static BACKTRACE: AtomicBool = ATOMIC_BOOL_INIT;
+static SCOPE_ABORTED: AtomicBool = ATOMIC_BOOL_INIT;
+static FLUSH_TIMEOUT_MS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+coroutine_local! {
+    /// nesting depth of the `DrainSection`s
+    static IN_DRAIN: Cell<usize> = {
+        Cell::new(0)
+    }
+}
+
+/// Marks the coroutine as using a drain, the hook doesn't log from inside
+pub(crate) struct DrainSection {
+    _private: (),
+}
+
+impl DrainSection {
+    pub(crate) fn enter() -> Self {
+        IN_DRAIN.with(|d| d.set(d.get() + 1));
+        DrainSection { _private: () }
+    }
+}
+
+impl Drop for DrainSection {
+    fn drop(&mut self) {
+        IN_DRAIN.with(|d| d.set(d.get() - 1));
+    }
+}
+
+fn in_drain() -> bool {
+    IN_DRAIN.with(|d| d.get() > 0)
+}
+
+/// Panic hook builder
+///
+/// ```ignore
+/// co_slog::PanicHook::new().backtrace(true).install();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PanicHook {
+    backtrace: bool,
+    scope_aborted: bool,
+    flush_timeout: Duration,
+}
+
+impl Default for PanicHook {
+    fn default() -> Self {
+        PanicHook::new()
+    }
+}
+
+impl PanicHook {
+    /// Create the panic hook with default settings
+    pub fn new() -> Self {
+        PanicHook {
+            backtrace: false,
+            scope_aborted: false,
+            flush_timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Attach a backtrace to the panic record
+    pub fn backtrace(mut self, enable: bool) -> Self {
+        self.backtrace = enable;
+        self
+    }
+
+    /// Let every `ScopeGuard` unwinding during a panic log a "scope aborted" record
+    pub fn scope_aborted(mut self, enable: bool) -> Self {
+        self.scope_aborted = enable;
+        self
+    }
+
+    /// Max time to wait for the `AsyncDrain` workers to flush
+    pub fn flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// Install the panic hook
+    ///
+    /// The hook is installed only once, calling it again updates the settings.
+    pub fn install(self) {
+        self.apply();
+        ACTIVE.store(true, Ordering::SeqCst);
+
+        INSTALL.call_once(|| {
+            let prev = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if !ACTIVE.load(Ordering::SeqCst) || in_drain() {
+                    return prev(info);
+                }
+
+                let payload = match info.payload().downcast_ref::<&str>() {
+                    Some(s) => *s,
+                    None => match info.payload().downcast_ref::<String>() {
+                        Some(s) => &s[..],
+                        None => "Box<Any>",
+                    },
+                };
+                let location = info.location()
+                    .map(|l| format!("{}:{}", l.file(), l.line()))
+                    .unwrap_or_default();
+
+                // the record could go to the worker's own full queue
+                if !async_drain::is_worker() {
+                    let _section = DrainSection::enter();
+                    with_logger(|l| if BACKTRACE.load(Ordering::Relaxed) {
+                        let bt = format!("{:?}", Backtrace::new());
+                        slog_crit!(l, "panicked: {}", payload;
+                                   "location" => location, "backtrace" => bt)
+                    } else {
+                        slog_crit!(l, "panicked: {}", payload; "location" => location)
+                    });
+                }
+
+                let timeout = FLUSH_TIMEOUT_MS.load(Ordering::Relaxed) as u64;
+                async_drain::flush_all(Duration::from_millis(timeout));
+                prev(info);
+            }));
+        });
+    }
+}
+
+impl PanicHook {
+    fn apply(&self) {
+        let timeout_ms = self.flush_timeout.as_secs() * 1_000 +
+            u64::from(self.flush_timeout.subsec_nanos() / 1_000_000);
+        BACKTRACE.store(self.backtrace, Ordering::Relaxed);
+        SCOPE_ABORTED.store(self.scope_aborted, Ordering::Relaxed);
+        FLUSH_TIMEOUT_MS.store(timeout_ms as usize, Ordering::Relaxed);
+    }
+}
+
+/// Install the panic hook with default settings
+///
+/// See `PanicHook`.
+pub fn install_panic_hook() {
+    PanicHook::new().install()
+}
+
+/// should the unwinding `ScopeGuard` log a "scope aborted" record
+pub(crate) fn report_aborted_scope() -> bool {
+    ACTIVE.load(Ordering::SeqCst) && SCOPE_ABORTED.load(Ordering::Relaxed)
+}
+
+/// the hook installed for one test, on drop it's passing the panics through
+/// to the previous hook again with the default settings
+///
+/// Hold `global::test_lock()` with it, the hook is process wide.
+#[cfg(test)]
+pub(crate) struct TestHook {
+    _private: (),
+}
+
+#[cfg(test)]
+impl TestHook {
+    pub(crate) fn install(hook: PanicHook) -> Self {
+        hook.install();
+        TestHook { _private: () }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestHook {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+        PanicHook::new().apply();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use slog::Level;
+
+    use super::{PanicHook, TestHook};
+    use global;
+    use set_logger;
+    use test_drains::recorder;
+
+    #[test]
+    fn logs_panic_through_scope() {
+        let _lock = global::test_lock();
+        let _hook = TestHook::install(PanicHook::new());
+        let (logger, logged) = recorder();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = set_logger(logger.clone());
+            panic!("boom");
+        }));
+        assert!(res.is_err());
+
+        let entries = logged.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].level, Level::Critical);
+        assert_eq!(entries[0].msg, "panicked: boom");
+        let location = entries[0].get("location").unwrap();
+        assert!(location.starts_with(file!()), "{}", location);
+    }
+
+    #[test]
+    fn logs_aborted_scope() {
+        let _lock = global::test_lock();
+        let _hook = TestHook::install(PanicHook::new().scope_aborted(true));
+        let (logger, logged) = recorder();
+
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = set_logger(logger.clone());
+            panic!("boom");
+        }));
+
+        let entries = logged.entries();
+        assert_eq!(logged.messages(), vec!["panicked: boom", "scope aborted"]);
+        assert_eq!(entries[1].level, Level::Error);
+        assert_eq!(entries[1].get("depth"), Some("0"));
+    }
+
+    #[test]
+    fn off_after_test() {
+        let _lock = global::test_lock();
+        drop(TestHook::install(PanicHook::new().scope_aborted(true)));
+        let (logger, logged) = recorder();
+
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = set_logger(logger.clone());
+            panic!("boom");
+        }));
+        assert!(logged.entries().is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! Signal driven verbosity control
+//!
+//! Once installed, `SIGUSR1` raises the levels of the `EnvDrain` rules by one
+//! step and `SIGUSR2` lowers them, see `FilterHandle::shift_verbosity`. The
+//! signal handler only writes to a pipe, a listener thread applies the change
+//! and logs it.
+//!
+//! ```text
+//! kill -USR1 <pid>  # info -> debug
+//! kill -USR2 <pid>  # debug -> info
+//! ```
+
+use std::{io, mem, ptr, thread};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use libc;
+use slog::{self, Level};
+use env_drain::FilterHandle;
+use global;
+use with_logger;
+
+/// write end of the signal pipe
+static PIPE_WR: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// raise and lower the verbosity
+const SIGNALS: [libc::c_int; 2] = [libc::SIGUSR1, libc::SIGUSR2];
+
+struct SignalState {
+    installed: bool,
+    handles: Vec<FilterHandle>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<SignalState> = {
+        Mutex::new(SignalState {
+            installed: false,
+            handles: Vec::new(),
+        })
+    };
+}
+
+/// Verbosity signals listener builder
+///
+/// Without any registered handle the signals control the default global
+/// logger (the `RUST_LOG` one on stderr).
+///
+/// ```ignore
+/// let (drain, handle) = co_slog::EnvDrain::new(drain).build_with_handle();
+/// co_slog::VerbositySignals::new().handle(handle).install()?;
+/// ```
+#[derive(Default)]
+pub struct VerbositySignals {
+    handles: Vec<FilterHandle>,
+}
+
+impl VerbositySignals {
+    /// Create the listener builder
+    pub fn new() -> Self {
+        VerbositySignals { handles: Vec::new() }
+    }
+
+    /// Register the `EnvDrain` rules controlled by the signals
+    pub fn handle(mut self, handle: FilterHandle) -> Self {
+        self.handles.push(handle);
+        self
+    }
+
+    /// Install the signal handlers and start the listener
+    ///
+    /// The listener is started only once, calling it again registers more
+    /// handles.
+    pub fn install(self) -> io::Result<()> {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.installed {
+            install_handlers()?;
+            state.installed = true;
+        }
+        state.handles.extend(self.handles);
+        Ok(())
+    }
+}
+
+/// Install the verbosity signals listener for the default global logger
+///
+/// See `VerbositySignals`.
+pub fn listen_verbosity_signals() -> io::Result<()> {
+    VerbositySignals::new().install()
+}
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    let byte: u8 = if sig == libc::SIGUSR1 { b'+' } else { b'-' };
+    let fd = PIPE_WR.load(Ordering::Relaxed) as libc::c_int;
+    // only async signal safe calls in here, a full pipe just loses the signal
+    unsafe {
+        // the interrupted code must not see the errno of our `write`
+        let errno = errno_location();
+        let saved = *errno;
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        *errno = saved;
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "redox"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+/// create the pipe, install the handlers and start the listener
+///
+/// On error everything done so far is undone, so `install` can be retried.
+fn install_handlers() -> io::Result<()> {
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the handler must never block on a full pipe
+    unsafe {
+        let flags = libc::fcntl(fds[1], libc::F_GETFL);
+        libc::fcntl(fds[1], libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+    PIPE_WR.store(fds[1] as usize, Ordering::Relaxed);
+
+    let mut prev: [libc::sigaction; 2] = unsafe { mem::zeroed() };
+    for (i, sig) in SIGNALS.iter().enumerate() {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_signal as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(*sig, &action, &mut prev[i]) != 0 {
+                let err = io::Error::last_os_error();
+                return undo(&prev[..i], &fds, err);
+            }
+        }
+    }
+
+    let rx = unsafe { File::from_raw_fd(fds[0]) };
+    let spawned = thread::Builder::new()
+        .name("co_slog-signal".to_owned())
+        .spawn(move || listen(rx));
+    if let Err(e) = spawned {
+        // the closure owning `rx` is dropped, its fd is closed already
+        return undo(&prev, &fds[1..], e);
+    }
+    Ok(())
+}
+
+/// restore the previous handlers and close the pipe
+fn undo(prev: &[libc::sigaction], fds: &[libc::c_int], err: io::Error) -> io::Result<()> {
+    for (sig, action) in SIGNALS.iter().zip(prev) {
+        unsafe {
+            libc::sigaction(*sig, action, ptr::null_mut());
+        }
+    }
+    PIPE_WR.store(0, Ordering::Relaxed);
+    for fd in fds {
+        unsafe {
+            libc::close(*fd);
+        }
+    }
+    Err(err)
+}
+
+fn listen(mut rx: File) {
+    let mut buf = [0u8; 1];
+    loop {
+        match rx.read(&mut buf) {
+            Ok(1) => {}
+            Ok(_) => return,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+        let v = if buf[0] == b'+' { 1 } else { -1 };
+
+        let handles = {
+            let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+            state.handles.clone()
+        };
+        if handles.is_empty() {
+            shift(&global::default_filter(), v);
+        }
+        for handle in &handles {
+            shift(handle, v);
+        }
+    }
+}
+
+/// shift the verbosity and log the change
+fn shift(handle: &FilterHandle, v: i8) {
+    static LOCATION: slog::RecordLocation = slog::RecordLocation {
+        file: file!(),
+        line: line!(),
+        column: column!(),
+        function: "",
+        module: module_path!(),
+    };
+
+    let max_level = handle.shift_verbosity(v);
+    // log at a level that is let through by the new rules
+    let level = match Level::from_usize(::std::cmp::min(max_level.as_usize(),
+                                                        Level::Info.as_usize())) {
+        Some(level) => level,
+        None => return,
+    };
+    let s = slog::RecordStatic {
+        location: &LOCATION,
+        level: level,
+        tag: "",
+    };
+    let msg = if v > 0 { "raised" } else { "lowered" };
+    let verbosity = handle.verbosity();
+    with_logger(|l| {
+        l.log(&slog::Record::new(&s,
+                                 &format_args!("log verbosity {}", msg),
+                                 b!("verbosity" => verbosity, "max_level" => max_level.as_str())))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use libc;
+    use slog::{self, Level};
+    use env_drain::LogBuilder;
+    use global::{self, set_global_logger};
+    use test_drains::recorder;
+    use super::VerbositySignals;
+
+    #[test]
+    fn sigusr1_raises_verbosity() {
+        let _lock = global::test_lock();
+        let (logger, recorder) = recorder();
+        let _guard = set_global_logger(logger);
+        let (_drain, handle) = LogBuilder::new(slog::Discard).parse("info").build_with_handle();
+        VerbositySignals::new().handle(handle.clone()).install().unwrap();
+
+        assert_eq!(handle.verbosity(), 0);
+        assert_eq!(unsafe { libc::raise(libc::SIGUSR1) }, 0);
+
+        // the listener thread applies the change and logs it
+        let start = Instant::now();
+        while recorder.entries().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10), "no verbosity change logged");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.verbosity(), 1);
+        let entries = recorder.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].msg, "log verbosity raised");
+        assert_eq!(entries[0].level, Level::Info);
+        assert_eq!(entries[0].get("verbosity"), Some("1"));
+        assert_eq!(entries[0].get("max_level"), Some("DEBUG"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Portable scope snapshots
+//!
+//! A `ScopeSnapshot` captures the logging scope of the current coroutine as a
+//! `Send + Clone` value, so it can travel with a job through a queue and be
+//! reinstated by the coroutine that processes it.
+
+use slog::Logger;
+use {current_scope, ScopeGuard, TL_SCOPES};
+
+/// Snapshot of the logging scope
+///
+/// ```ignore
+/// let snapshot = co_slog::ScopeSnapshot::capture();
+/// tx.send((job, snapshot));
+/// // in the worker coroutine
+/// let (job, snapshot) = rx.recv().unwrap();
+/// let _guard = snapshot.enter();
+/// ```
+#[derive(Clone)]
+pub struct ScopeSnapshot {
+    // bottom first
+    loggers: Vec<Logger>,
+}
+
+impl ScopeSnapshot {
+    /// Capture the current scope logger
+    ///
+    /// The snapshot is empty if no scope logger is set, entering it would
+    /// keep logging through the global logger.
+    pub fn capture() -> Self {
+        ScopeSnapshot { loggers: current_scope().into_iter().collect() }
+    }
+
+    /// Capture the whole scope stack
+    pub fn capture_stack() -> Self {
+        let loggers = TL_SCOPES.with(|s| {
+            s.borrow().iter().map(|e| e.logger.clone()).collect()
+        });
+        ScopeSnapshot { loggers: loggers }
+    }
+
+    /// Reinstate the snapshot in the current coroutine
+    ///
+    /// The captured loggers are pushed on top of the current scope stack and
+    /// popped when the returned guard is dropped.
+    pub fn enter(&self) -> SnapshotGuard {
+        SnapshotGuard {
+            guards: self.loggers.iter().cloned().map(ScopeGuard::new).collect(),
+        }
+    }
+}
+
+/// Snapshot guard, when dropped would pop the reinstated loggers
+pub struct SnapshotGuard {
+    guards: Vec<ScopeGuard>,
+}
+
+impl Drop for SnapshotGuard {
+    fn drop(&mut self) {
+        // pop in the reverse push order
+        while let Some(guard) = self.guards.pop() {
+            drop(guard);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Timed span guards built on the scope stack
+//!
+//! A span pushes a child of the current scope logger with `span` and
+//! `span_depth` keys, logs an enter record, and on drop logs an exit record
+//! with the elapsed time and the outcome (`ok` or `panic`).
+//!
+//! See the `span!` macro.
+
+use std::cell::Cell;
+use std::thread;
+use std::time::Instant;
+use slog::{self, Logger};
+use ScopeGuard;
+
+/// nesting depth of the live spans
+coroutine_local! {
+    static SPAN_DEPTH: Cell<usize> = {
+        Cell::new(0)
+    }
+}
+
+/// Span guard, when dropped would log the exit record and pop the span logger
+///
+/// Created by the `span!` macro.
+#[must_use]
+pub struct SpanGuard {
+    logger: Logger,
+    start: Instant,
+    // pop the scope after the exit record is logged
+    _scope: ScopeGuard,
+}
+
+impl SpanGuard {
+    /// Enter a new span with the extra key-values
+    pub fn new<T>(name: &'static str, values: slog::OwnedKV<T>) -> Self
+    where
+        T: slog::SendSyncRefUnwindSafeKV + 'static,
+    {
+        let depth = SPAN_DEPTH.with(|d| {
+            d.set(d.get() + 1);
+            d.get()
+        });
+        let logger = ::logger()
+            .new(o!("span" => name, "span_depth" => depth))
+            .new(values);
+        slog_debug!(logger, "span enter");
+
+        SpanGuard {
+            _scope: ScopeGuard::new(logger.clone()),
+            logger: logger,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let elapsed_us = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_nanos() / 1_000);
+        if thread::panicking() {
+            slog_error!(self.logger, "span exit"; "elapsed_us" => elapsed_us, "outcome" => "panic");
+        } else {
+            slog_debug!(self.logger, "span exit"; "elapsed_us" => elapsed_us, "outcome" => "ok");
+        }
+        SPAN_DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
@@ -0,0 +1,86 @@
+//! Drains shared by the unit tests
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use slog::{self, Drain, Key, Level, Logger, OwnedKVList, Record, Serializer, KV};
+
+/// count the records written
+pub struct Counter(pub Arc<AtomicUsize>);
+
+impl Drain for Counter {
+    type Ok = ();
+    type Err = slog::Never;
+    fn log(&self, _: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// a record kept by `Recorder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub level: Level,
+    pub msg: String,
+    /// the record key-values then the logger ones, formatted
+    pub kv: Vec<(String, String)>,
+}
+
+impl Entry {
+    /// the first value of the key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.kv.iter().find(|kv| kv.0 == key).map(|kv| &kv.1[..])
+    }
+}
+
+/// format the key-values
+struct KvStrings(Vec<(String, String)>);
+
+impl Serializer for KvStrings {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
+
+/// keep the records, the clones share them
+#[derive(Clone, Default)]
+pub struct Recorder(Arc<Mutex<Vec<Entry>>>);
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    /// the records kept so far
+    pub fn entries(&self) -> Vec<Entry> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// the messages kept so far
+    pub fn messages(&self) -> Vec<String> {
+        self.entries().into_iter().map(|e| e.msg).collect()
+    }
+}
+
+impl Drain for Recorder {
+    type Ok = ();
+    type Err = slog::Never;
+    fn log(&self, r: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let mut kv = KvStrings(Vec::new());
+        let _ = r.kv().serialize(r, &mut kv);
+        let _ = values.serialize(r, &mut kv);
+        self.0.lock().unwrap().push(Entry {
+            level: r.level(),
+            msg: r.msg().to_string(),
+            kv: kv.0,
+        });
+        Ok(())
+    }
+}
+
+/// a logger writing to a new `Recorder`
+pub fn recorder() -> (Logger, Recorder) {
+    let recorder = Recorder::new();
+    (Logger::root(recorder.clone(), o!()), recorder)
+}
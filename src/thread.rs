@@ -0,0 +1,63 @@
+//! Thread helpers that carry the logging scope into the new thread
+//!
+//! Work offloaded to a plain OS thread (blocking IO, CPU heavy tasks) would
+//! otherwise lose its logging scope. The functions here mirror `std::thread`
+//! and push the caller's current scope logger in the new thread.
+
+use std::io;
+use std::thread::{self, JoinHandle};
+use coroutine::with_parent;
+use current_scope;
+
+/// Spawn a thread that inherits the current scope logger
+///
+/// This is a wrapper of `std::thread::spawn`, the scope is popped when the
+/// thread closure returns.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f).expect("failed to spawn thread")
+}
+
+/// Thread factory that inherits the current scope logger
+///
+/// This is a wrapper of `std::thread::Builder`.
+#[derive(Debug)]
+pub struct Builder {
+    inner: thread::Builder,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    /// Create a new thread builder
+    pub fn new() -> Self {
+        Builder { inner: thread::Builder::new() }
+    }
+
+    /// Names the thread-to-be
+    pub fn name(self, name: String) -> Self {
+        Builder { inner: self.inner.name(name) }
+    }
+
+    /// Sets the size of the stack for the new thread
+    pub fn stack_size(self, size: usize) -> Self {
+        Builder { inner: self.inner.stack_size(size) }
+    }
+
+    /// Spawn a new thread that inherits the current scope logger
+    pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let parent = current_scope();
+        self.inner.spawn(move || with_parent(parent, f))
+    }
+}